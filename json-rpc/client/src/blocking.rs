@@ -7,11 +7,38 @@ use reqwest::{
     blocking::{Client, ClientBuilder},
     Url,
 };
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 const JSON_RPC_TIMEOUT_MS: u64 = 20_000;
 const MAX_JSON_RPC_RETRY_COUNT: u64 = 3;
 
+/// Connection settings of the http client backing a `JsonRpcClient`
+///
+/// The defaults are reqwest's own, a client built with them behaves like `JsonRpcClient::new`
+/// always did.
+#[derive(Clone, Debug)]
+pub struct ConnectionOptions {
+    /// Speak HTTP/2 right away, without negotiating it; the server must support it
+    pub http2_prior_knowledge: bool,
+    /// How long an idle pooled connection is kept, `None` to keep it forever
+    pub pool_idle_timeout: Option<Duration>,
+    /// Maximum idle connections kept per host, `None` for no limit
+    pub pool_max_idle_per_host: Option<usize>,
+    /// Interval of the TCP keepalive probes, `None` to leave them off
+    pub tcp_keepalive: Option<Duration>,
+}
+
+impl Default for ConnectionOptions {
+    fn default() -> Self {
+        Self {
+            http2_prior_knowledge: false,
+            pool_idle_timeout: Some(Duration::from_secs(90)),
+            pool_max_idle_per_host: None,
+            tcp_keepalive: None,
+        }
+    }
+}
+
 pub struct JsonRpcClient {
     url: Url,
     client: Client,
@@ -19,8 +46,22 @@ pub struct JsonRpcClient {
 
 impl JsonRpcClient {
     pub fn new(url: Url) -> Result<Self> {
+        Self::with_options(url, ConnectionOptions::default())
+    }
+
+    pub fn with_options(url: Url, options: ConnectionOptions) -> Result<Self> {
+        let mut builder = ClientBuilder::new()
+            .use_native_tls()
+            .pool_idle_timeout(options.pool_idle_timeout)
+            .tcp_keepalive(options.tcp_keepalive);
+        if let Some(max_idle) = options.pool_max_idle_per_host {
+            builder = builder.pool_max_idle_per_host(max_idle);
+        }
+        if options.http2_prior_knowledge {
+            builder = builder.http2_prior_knowledge();
+        }
         Ok(Self {
-            client: ClientBuilder::new().use_native_tls().build()?,
+            client: builder.build()?,
             url,
         })
     }
@@ -28,18 +69,19 @@ impl JsonRpcClient {
     /// Sends a JSON RPC batched request.
     /// Returns a vector of responses s.t. response order matches the request order
     pub fn execute(&self, batch: JsonRpcBatch) -> Result<Vec<Result<JsonRpcResponse>>> {
+        self.execute_with_timeout(batch, Duration::from_millis(JSON_RPC_TIMEOUT_MS))
+    }
+
+    /// Like `execute`, with `timeout` bounding the single attempt instead of the default one
+    pub fn execute_with_timeout(
+        &self,
+        batch: JsonRpcBatch,
+        timeout: Duration,
+    ) -> Result<Vec<Result<JsonRpcResponse>>> {
         if batch.requests.is_empty() {
             return Ok(vec![]);
         }
-        let request = batch.json_request();
-
-        //retry send
-        let response = self
-            .send_with_retry(request)?
-            .error_for_status()
-            .map_err(|e| format_err!("Server returned error: {:?}", e))?;
-
-        let response = process_batch_response(batch.clone(), response.json()?)?;
+        let response = process_batch_response(batch.clone(), self.execute_raw(&batch, timeout)?)?;
         ensure!(
             batch.requests.len() == response.len(),
             "received unexpected number of responses in batch"
@@ -47,27 +89,54 @@ impl JsonRpcClient {
         Ok(response)
     }
 
+    /// Sends a JSON RPC batched request, returning the raw json responses in the server's order
+    ///
+    /// Makes a single attempt, retrying is left to the caller.
+    pub fn execute_raw(&self, batch: &JsonRpcBatch, timeout: Duration) -> Result<Vec<serde_json::Value>> {
+        let request = batch.json_request();
+
+        let response = self
+            .send(&request, timeout)?
+            .error_for_status()
+            .map_err(|e| format_err!("Server returned error: {:?}", e))?;
+        Ok(response.json()?)
+    }
+
     // send with retry
     pub fn send_with_retry(
         &self,
         request: serde_json::Value,
     ) -> Result<reqwest::blocking::Response> {
-        let mut response = self.send(&request);
+        self.send_with_retry_timeout(request, Duration::from_millis(JSON_RPC_TIMEOUT_MS))
+    }
+
+    /// Sends with retries, each attempt only getting what is left of `timeout`
+    fn send_with_retry_timeout(
+        &self,
+        request: serde_json::Value,
+        timeout: Duration,
+    ) -> Result<reqwest::blocking::Response> {
+        let deadline = Instant::now() + timeout;
+        let mut response = self.send(&request, timeout);
         let mut try_cnt = 0;
 
         // retry if send fails
         while try_cnt < MAX_JSON_RPC_RETRY_COUNT && response.is_err() {
-            response = self.send(&request);
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left == Duration::from_secs(0) {
+                break;
+            }
+            response = self.send(&request, left);
             try_cnt += 1;
         }
         response
     }
 
-    fn send(&self, request: &serde_json::Value) -> Result<reqwest::blocking::Response> {
+    fn send(&self, request: &serde_json::Value, timeout: Duration) -> Result<reqwest::blocking::Response> {
         self.client
             .post(self.url.clone())
             .json(request)
-            .timeout(Duration::from_millis(JSON_RPC_TIMEOUT_MS))
+            .timeout(timeout)
             .send()
             .map_err(Into::into)
     }
@@ -5,7 +5,7 @@ mod blocking;
 mod client;
 mod response;
 
-pub use blocking::JsonRpcClient;
+pub use blocking::{ConnectionOptions, JsonRpcClient};
 pub use client::{
     get_response_from_batch, process_batch_response, JsonRpcAsyncClient, JsonRpcAsyncClientError,
     JsonRpcBatch,
@@ -0,0 +1,62 @@
+//! The accounts file, listing extra addresses to monitor one per line
+
+use std::fs;
+
+use diem_types::account_address::AccountAddress;
+
+use crate::error::Error;
+
+/// Reads the addresses of an accounts file, as full length lowercase hex without `0x`
+///
+/// Blank lines and `#` comments, whole line or trailing, are ignored. Any other line must be an
+/// account address, the whole file is rejected otherwise.
+pub fn read_accounts_file(path: &str) -> Result<Vec<String>, Error> {
+    parse_accounts(path, &fs::read_to_string(path)?)
+}
+
+fn parse_accounts(path: &str, content: &str) -> Result<Vec<String>, Error> {
+    let mut addresses = Vec::new();
+    for (i, line) in content.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let hex = line.trim_start_matches("0x");
+        let address = AccountAddress::from_hex_literal(&format!("0x{}", hex))
+            .map_err(|_| Error::InvalidConfig(format!("{}:{}: bad account address {}", path, i + 1, line)))?;
+        // Short forms like 0x1 are padded, so that every account has a single spelling
+        let address = hex::encode(address.to_vec());
+        if !addresses.contains(&address) {
+            addresses.push(address);
+        }
+    }
+    Ok(addresses)
+}
+
+/// The addresses of `new` missing from `old`, and those of `old` missing from `new`
+pub fn diff_accounts(old: &[String], new: &[String]) -> (Vec<String>, Vec<String>) {
+    let added = new.iter().filter(|a| !old.contains(a)).cloned().collect();
+    let removed = old.iter().filter(|a| !new.contains(a)).cloned().collect();
+    (added, removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_addresses_ignoring_comments() {
+        let content = "# fleet\n\n0xD4F0C053205BA934BB2AC0C4E8479E77  # hot wallet\nd4f0c053205ba934bb2ac0c4e8479e77\n  1  \n";
+        let addresses = parse_accounts("accounts", content).unwrap();
+        assert_eq!(addresses, vec!["d4f0c053205ba934bb2ac0c4e8479e77".to_string(), "00000000000000000000000000000001".to_string()]);
+
+        assert!(matches!(parse_accounts("accounts", "0x1\nnot an address\n"), Err(Error::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn diff_of_reloaded_accounts() {
+        let old = vec!["1".to_string(), "2".to_string()];
+        let new = vec!["2".to_string(), "3".to_string()];
+        assert_eq!(diff_accounts(&old, &new), (vec!["3".to_string()], vec!["1".to_string()]));
+    }
+}
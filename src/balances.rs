@@ -0,0 +1,197 @@
+//! Cross-checks of account balances against the transactions seen by the bridge
+
+use std::collections::BTreeMap;
+
+use diem_json_rpc_client::views::{ScriptView, TransactionDataView, TransactionView, VMStatusView};
+
+/// The change `transaction` made to the balances of `account` (lowercase hex), per currency
+///
+/// Covers the gas paid by the sender and the peer to peer and mint scripts. Other scripts move
+/// funds in ways the view doesn't describe: `None` when one of them was executed.
+pub fn balance_delta(account: &str, transaction: &TransactionView) -> Option<BTreeMap<String, i128>> {
+    let mut delta = BTreeMap::new();
+    let (sender, gas_unit_price, gas_currency, script) = match &transaction.transaction {
+        TransactionDataView::UserTransaction { sender, gas_unit_price, gas_currency, script, .. } => {
+            (sender.0.to_ascii_lowercase(), *gas_unit_price, gas_currency, script)
+        }
+        _ => return Some(delta),
+    };
+    let mut add = |currency: &str, amount: i128| *delta.entry(currency.to_string()).or_insert(0) += amount;
+
+    if sender == account {
+        add(gas_currency, -((transaction.gas_used as i128) * (gas_unit_price as i128)));
+    }
+    // A failed transaction only pays for its gas
+    if transaction.vm_status != VMStatusView::Executed {
+        return Some(delta);
+    }
+    match script {
+        ScriptView::PeerToPeer { receiver, amount, currency, .. } => {
+            if sender == account {
+                add(currency, -(*amount as i128));
+            }
+            if receiver.to_ascii_lowercase() == account {
+                add(currency, *amount as i128);
+            }
+        }
+        ScriptView::Mint { receiver, amount, currency, .. } => {
+            if receiver.to_ascii_lowercase() == account {
+                add(currency, *amount as i128);
+            }
+        }
+        _ => return None,
+    }
+    delta.retain(|_, amount| *amount != 0);
+    Some(delta)
+}
+
+/// An account's balances at a ledger version, and the deltas of the transactions seen after it
+///
+/// A `None` delta is a transaction whose effect isn't known, see `balance_delta`.
+#[derive(Debug, Default)]
+pub struct BalanceLedger {
+    baseline: Option<(u64, BTreeMap<String, u64>)>,
+    deltas: BTreeMap<u64, Option<BTreeMap<String, i128>>>,
+}
+
+impl BalanceLedger {
+    /// Records the delta of the transaction at `version`, once however often it's seen
+    pub fn record(&mut self, version: u64, delta: Option<BTreeMap<String, i128>>) {
+        if self.baseline.as_ref().map_or(false, |(baseline, _)| version <= *baseline) {
+            return;
+        }
+        self.deltas.insert(version, delta);
+    }
+
+    /// Compares `balances` at `version` with the baseline plus the deltas recorded up to
+    /// `version`, then makes them the new baseline
+    ///
+    /// Returns how far each diverging currency is from the expected balance (actual - expected),
+    /// nothing on the first call nor when a transaction of unknown effect is in the range.
+    pub fn check(&mut self, version: u64, balances: BTreeMap<String, u64>) -> BTreeMap<String, i128> {
+        let mut discrepancies = BTreeMap::new();
+        if let Some((baseline_version, baseline)) = &self.baseline {
+            // An endpoint behind the last check has nothing new to compare
+            if version <= *baseline_version {
+                return discrepancies;
+            }
+            let mut expected: BTreeMap<String, i128> = baseline.iter()
+                .map(|(currency, amount)| (currency.clone(), *amount as i128))
+                .collect();
+            let mut known = true;
+            for delta in self.deltas.range(baseline_version + 1..=version).map(|(_, delta)| delta) {
+                match delta {
+                    Some(delta) => for (currency, amount) in delta {
+                        *expected.entry(currency.clone()).or_insert(0) += amount;
+                    },
+                    None => known = false,
+                }
+            }
+            // Nothing to compare with, the balances become the new baseline all the same
+            let currencies = expected.keys().chain(balances.keys()).filter(|_| known);
+            for currency in currencies {
+                let actual = balances.get(currency).map_or(0, |amount| *amount as i128);
+                let discrepancy = actual - expected.get(currency).cloned().unwrap_or(0);
+                if discrepancy != 0 {
+                    discrepancies.insert(currency.clone(), discrepancy);
+                }
+            }
+        }
+        self.deltas = self.deltas.split_off(&(version + 1));
+        self.baseline = Some((version, balances));
+        discrepancies
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diem_json_rpc_client::views::BytesView;
+
+    fn payment(version: u64, sender: &str, receiver: &str, amount: u64, vm_status: VMStatusView) -> TransactionView {
+        TransactionView {
+            version,
+            transaction: TransactionDataView::UserTransaction {
+                sender: BytesView(sender.to_string()),
+                signature_scheme: String::new(),
+                signature: BytesView(String::new()),
+                public_key: BytesView(String::new()),
+                sequence_number: 0,
+                chain_id: 2,
+                max_gas_amount: 0,
+                gas_unit_price: 1,
+                gas_currency: "XUS".to_string(),
+                expiration_timestamp_secs: 0,
+                script_hash: BytesView(String::new()),
+                script_bytes: BytesView(String::new()),
+                script: ScriptView::PeerToPeer {
+                    receiver: receiver.to_string(),
+                    amount,
+                    currency: "XUS".to_string(),
+                    metadata: BytesView(String::new()),
+                    metadata_signature: BytesView(String::new()),
+                },
+            },
+            hash: String::new(),
+            bytes: BytesView(String::new()),
+            events: vec![],
+            vm_status,
+            gas_used: 5,
+        }
+    }
+
+    #[test]
+    fn deltas_of_payments() {
+        let sent = payment(1, "AA", "bb", 100, VMStatusView::Executed);
+        assert_eq!(balance_delta("aa", &sent).unwrap().get("XUS"), Some(&-105));
+        assert_eq!(balance_delta("bb", &sent).unwrap().get("XUS"), Some(&100));
+        let failed = payment(2, "aa", "bb", 100, VMStatusView::OutOfGas);
+        assert_eq!(balance_delta("aa", &failed).unwrap().get("XUS"), Some(&-5));
+        assert!(balance_delta("bb", &failed).unwrap().is_empty());
+    }
+
+    #[test]
+    fn unmodelled_scripts_leave_the_balance_unknown() {
+        let balances = |amount: u64| vec![("XUS".to_string(), amount)].into_iter().collect();
+        let mut other = payment(4, "aa", "bb", 0, VMStatusView::Executed);
+        if let TransactionDataView::UserTransaction { script, .. } = &mut other.transaction {
+            *script = ScriptView::Unknown {};
+        }
+        assert!(balance_delta("aa", &other).is_none());
+        // Failing, it's known to only have paid for its gas
+        other.vm_status = VMStatusView::OutOfGas;
+        assert_eq!(balance_delta("aa", &other).unwrap().get("XUS"), Some(&-5));
+        other.vm_status = VMStatusView::Executed;
+
+        let mut ledger = BalanceLedger::default();
+        assert!(ledger.check(3, balances(1_000)).is_empty());
+        ledger.record(4, balance_delta("aa", &other));
+        ledger.record(5, balance_delta("aa", &payment(5, "aa", "bb", 10, VMStatusView::Executed)));
+        // Whatever the script moved, no divergence is reported
+        assert!(ledger.check(6, balances(700)).is_empty());
+
+        // Known again from the new baseline on
+        ledger.record(7, balance_delta("aa", &payment(7, "aa", "bb", 10, VMStatusView::Executed)));
+        assert!(ledger.check(8, balances(685)).is_empty());
+        assert_eq!(ledger.check(9, balances(600)).get("XUS"), Some(&-85));
+    }
+
+    #[test]
+    fn discrepancies_against_seen_transactions() {
+        let balances = |amount: u64| vec![("XUS".to_string(), amount)].into_iter().collect();
+        let mut ledger = BalanceLedger::default();
+        ledger.record(3, balance_delta("bb", &payment(3, "aa", "bb", 10, VMStatusView::Executed)));
+        assert!(ledger.check(5, balances(1_000)).is_empty());
+
+        // Already part of the baseline
+        ledger.record(4, balance_delta("bb", &payment(4, "aa", "bb", 10, VMStatusView::Executed)));
+        ledger.record(7, balance_delta("bb", &payment(7, "aa", "bb", 30, VMStatusView::Executed)));
+        // Seen, but after the checked version
+        ledger.record(12, balance_delta("bb", &payment(12, "aa", "bb", 50, VMStatusView::Executed)));
+        assert!(ledger.check(10, balances(1_030)).is_empty());
+
+        // A payment of 20 at version 11 was missed
+        let discrepancies = ledger.check(15, balances(1_100));
+        assert_eq!(discrepancies.get("XUS"), Some(&20));
+    }
+}
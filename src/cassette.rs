@@ -0,0 +1,112 @@
+//! Recording and replay of Diem rpc exchanges, to reproduce field issues offline
+
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::sync::Mutex;
+
+use anyhow::format_err;
+use diem_json_rpc_client::JsonRpcBatch;
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+
+use crate::error::Error;
+
+/// One line of a cassette file
+#[derive(Serialize, Deserialize)]
+struct Exchange {
+    /// Url of the endpoint the batch went to
+    #[serde(default)]
+    endpoint: String,
+    /// `(method, params)` of every request of the batch, ids left out
+    requests: Vec<(String, Vec<Value>)>,
+    /// Raw json-rpc responses, errors included
+    responses: Vec<Value>,
+}
+
+/// A file of rpc exchanges, either being appended to or served back in place of the endpoint
+pub enum Cassette {
+    Record { path: String },
+    /// Responses per endpoint and request shape, in recorded order; the last one is repeated once exhausted
+    Replay { exchanges: Mutex<HashMap<String, VecDeque<Vec<Value>>>> },
+}
+
+impl Cassette {
+    pub fn record(path: &str) -> Self {
+        Cassette::Record { path: path.to_string() }
+    }
+
+    pub fn replay(path: &str) -> Result<Self, Error> {
+        let mut exchanges = HashMap::<String, VecDeque<Vec<Value>>>::new();
+        for line in fs::read_to_string(path)?.lines().filter(|l| !l.trim().is_empty()) {
+            let exchange: Exchange = serde_json::from_str(line)?;
+            exchanges.entry(serde_json::to_string(&(&exchange.endpoint, &exchange.requests))?)
+                .or_default()
+                .push_back(exchange.responses);
+        }
+        Ok(Cassette::Replay { exchanges: Mutex::new(exchanges) })
+    }
+
+    pub fn is_replay(&self) -> bool {
+        matches!(self, Cassette::Replay { .. })
+    }
+
+    /// The recorded responses to a batch of the same shape as `batch` sent to `endpoint`
+    pub fn play(&self, endpoint: &str, batch: &JsonRpcBatch) -> anyhow::Result<Vec<Value>> {
+        let key = serde_json::to_string(&(endpoint, &batch.requests))?;
+        let exchanges = match self {
+            Cassette::Replay { exchanges } => exchanges,
+            Cassette::Record { .. } => return Err(format_err!("cassette is recording")),
+        };
+        let mut exchanges = exchanges.lock().unwrap();
+        let responses = exchanges.get_mut(&key).ok_or_else(|| format_err!("no recorded response for {}", key))?;
+        match responses.len() {
+            0 => Err(format_err!("no recorded response for {}", key)),
+            1 => Ok(responses[0].clone()),
+            _ => Ok(responses.pop_front().unwrap()),
+        }
+    }
+
+    /// Appends an exchange to the cassette file, a no-op when replaying
+    pub fn save(&self, endpoint: &str, batch: &JsonRpcBatch, responses: &[Value]) -> Result<(), Error> {
+        let path = match self {
+            Cassette::Record { path } => path,
+            Cassette::Replay { .. } => return Ok(()),
+        };
+        let exchange = Exchange { endpoint: endpoint.to_string(), requests: batch.requests.clone(), responses: responses.to_vec() };
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        writeln!(file, "{}", serde_json::to_string(&exchange)?)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn replays_in_recorded_order() {
+        let path = std::env::temp_dir().join(format!("pdiem-cassette-{}.jsonl", std::process::id()));
+        let path = path.to_str().unwrap();
+        let _ = fs::remove_file(path);
+
+        let mut batch = JsonRpcBatch::new();
+        batch.add_get_metadata_request(None);
+        let recorder = Cassette::record(path);
+        recorder.save("main", &batch, &[json!({ "id": 0, "result": 1 })]).unwrap();
+        recorder.save("main", &batch, &[json!({ "id": 0, "result": 2 })]).unwrap();
+        recorder.save("archive", &batch, &[json!({ "id": 0, "result": 3 })]).unwrap();
+
+        let player = Cassette::replay(path).unwrap();
+        assert_eq!(player.play("main", &batch).unwrap(), vec![json!({ "id": 0, "result": 1 })]);
+        assert_eq!(player.play("archive", &batch).unwrap(), vec![json!({ "id": 0, "result": 3 })]);
+        assert_eq!(player.play("main", &batch).unwrap(), vec![json!({ "id": 0, "result": 2 })]);
+        assert_eq!(player.play("main", &batch).unwrap(), vec![json!({ "id": 0, "result": 2 })]);
+
+        let mut other = JsonRpcBatch::new();
+        other.add_get_state_proof_request(0);
+        assert!(player.play("main", &other).is_err());
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,61 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use async_trait::async_trait;
+
+/// Source of wall-clock time and delays, so time-based logic can be driven manually in tests
+#[async_trait]
+pub trait Clock: Send + Sync {
+    fn now(&self) -> SystemTime;
+
+    async fn sleep(&self, duration: Duration);
+
+    /// Seconds since the unix epoch
+    fn now_secs(&self) -> u64 {
+        self.now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+    }
+}
+
+/// The real clock backed by `std::time` and the tokio timer
+pub struct SystemClock;
+
+#[async_trait]
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    async fn sleep(&self, duration: Duration) {
+        tokio::time::delay_for(duration).await;
+    }
+}
+
+/// A clock that only moves when advanced; sleeping advances it instantly
+#[cfg(test)]
+pub struct MockClock {
+    now: std::sync::Mutex<SystemTime>,
+}
+
+#[cfg(test)]
+impl MockClock {
+    pub fn new(now_secs: u64) -> Self {
+        MockClock {
+            now: std::sync::Mutex::new(UNIX_EPOCH + Duration::from_secs(now_secs)),
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        let mut now = self.now.lock().unwrap();
+        *now += duration;
+    }
+}
+
+#[cfg(test)]
+#[async_trait]
+impl Clock for MockClock {
+    fn now(&self) -> SystemTime {
+        *self.now.lock().unwrap()
+    }
+
+    async fn sleep(&self, duration: Duration) {
+        self.advance(duration);
+    }
+}
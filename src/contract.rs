@@ -0,0 +1,82 @@
+use async_trait::async_trait;
+use core::marker::PhantomData;
+use sp_core::sr25519;
+use std::cmp;
+use subxt::Signer;
+use subxt::system::AccountStoreExt;
+
+use crate::error::Error;
+use crate::runtimes;
+use crate::types::Runtime;
+
+pub type SrSigner = subxt::PairSigner<Runtime, sr25519::Pair>;
+pub type XtClient = subxt::Client<Runtime>;
+
+/// Where the bridge's contract commands end up
+#[async_trait]
+pub trait CommandSink: Send {
+    /// Submits an already encoded `Payload` to the Diem contract
+    async fn push_command(&mut self, contract_id: u32, payload: Vec<u8>) -> Result<(), Error>;
+}
+
+/// Pushes commands to the contract through a substrate node
+pub struct ChainSink {
+    client: XtClient,
+    signer: SrSigner,
+}
+
+impl ChainSink {
+    pub fn new(client: XtClient, signer: SrSigner) -> Self {
+        ChainSink { client, signer }
+    }
+
+    async fn update_signer_nonce(&mut self) -> Result<(), Error> {
+        let account_id = self.signer.account_id();
+        let nonce = self.client.account(account_id, None).await?.nonce;
+        let local_nonce = self.signer.nonce();
+        self.signer.set_nonce(cmp::max(nonce, local_nonce.unwrap_or(0)));
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl CommandSink for ChainSink {
+    async fn push_command(&mut self, contract_id: u32, payload: Vec<u8>) -> Result<(), Error> {
+        let call = runtimes::phala::PushCommandCall {
+            _runtime: PhantomData,
+            contract_id,
+            payload,
+        };
+
+        self.update_signer_nonce().await?;
+        let ret = self.client.submit(call, &self.signer).await;
+        if !ret.is_ok() {
+            println!("FailedToCallPushCommand: {:?}", ret);
+            return Err(Error::FailedToCallPushCommand);
+        }
+        self.signer.increment_nonce();
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[derive(Default)]
+pub struct MockSink {
+    pub commands: Vec<String>,
+    /// Pushes left to fail before the sink accepts commands again
+    pub failures: usize,
+}
+
+#[cfg(test)]
+#[async_trait]
+impl CommandSink for MockSink {
+    async fn push_command(&mut self, _contract_id: u32, payload: Vec<u8>) -> Result<(), Error> {
+        if self.failures > 0 {
+            self.failures -= 1;
+            return Err(Error::FailedToCallPushCommand);
+        }
+        self.commands.push(String::from_utf8(payload).expect("payloads are json"));
+        Ok(())
+    }
+}
@@ -0,0 +1,36 @@
+use diem_types::chain_id::{ChainId, NamedChain};
+
+use crate::error::Error;
+
+/// Currency codes registered on the named Diem chains
+const DIEM_CURRENCIES: [&str; 2] = ["XUS", "XDX"];
+
+/// The currency codes known on `chain_id`, `None` for chains the bridge has no registry of
+pub fn known_currencies(chain_id: ChainId) -> Option<&'static [&'static str]> {
+    NamedChain::from_chain_id(&chain_id).ok().map(|_| &DIEM_CURRENCIES[..])
+}
+
+/// Normalizes a currency code from the rpc, failing with `UnknownCurrency` if it isn't known on
+/// `chain_id`; codes on chains without a registry are passed through
+pub fn normalize_currency(chain_id: ChainId, currency: &str) -> Result<String, Error> {
+    let code = currency.trim().to_ascii_uppercase();
+    match known_currencies(chain_id) {
+        Some(known) if !known.contains(&code.as_str()) => Err(Error::UnknownCurrency(currency.to_string())),
+        _ => Ok(code),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn currencies_checked_on_named_chains() {
+        let testnet = ChainId::new(NamedChain::TESTNET.id());
+        assert_eq!(normalize_currency(testnet, "XUS").unwrap(), "XUS");
+        assert_eq!(normalize_currency(testnet, " xdx").unwrap(), "XDX");
+        assert!(matches!(normalize_currency(testnet, "XUSS"), Err(Error::UnknownCurrency(_))));
+
+        assert_eq!(normalize_currency(ChainId::new(42), "coin1").unwrap(), "COIN1");
+    }
+}
@@ -0,0 +1,99 @@
+//! Compact status of the sync loop for `pdiem watch`
+
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use crate::{BridgeStats, SyncSummary};
+
+/// What the dashboard shows, accumulated over the passes of this session
+#[derive(Debug, Default)]
+pub struct Dashboard {
+    passes: u64,
+    chain_version: u64,
+    verified_version: u64,
+    sync_lag: u64,
+    sync_lag_ema: f64,
+    /// Next sent sequence number to sync per account
+    sent_sequence: BTreeMap<String, u64>,
+    submitted: u64,
+    rpc_errors: u64,
+    last_error: Option<String>,
+}
+
+impl Dashboard {
+    pub fn update(&mut self, summary: &SyncSummary, stats: &BridgeStats, sent_sequence: &BTreeMap<String, u64>) {
+        self.passes += 1;
+        self.chain_version = stats.chain_version;
+        self.verified_version = stats.last_verified_version;
+        self.sync_lag = summary.sync_lag;
+        self.sync_lag_ema = summary.sync_lag_ema;
+        self.sent_sequence = sent_sequence.clone();
+        self.submitted = stats.transactions_submitted;
+        self.rpc_errors = stats.rpc_errors;
+        if let Some((address, error)) = summary.accounts.iter().find_map(|(addr, a)| a.error.as_ref().map(|e| (addr, e))) {
+            self.last_error = Some(format!("{}: {}", address, error));
+        }
+    }
+
+    pub fn record_error(&mut self, error: String) {
+        self.passes += 1;
+        self.last_error = Some(error);
+    }
+
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = vec![
+            format!("pass {}  latest chain version {}", self.passes, self.chain_version),
+            format!("verified version {}  lag {} (avg {:.1})", self.verified_version, self.sync_lag, self.sync_lag_ema),
+            format!("submitted this session: {}  rpc errors: {}", self.submitted, self.rpc_errors),
+        ];
+        for (address, sequence) in &self.sent_sequence {
+            lines.push(format!("  {}  sent seq {}", address, sequence));
+        }
+        lines.push(format!("last error: {}", self.last_error.as_deref().unwrap_or("none")));
+        lines
+    }
+
+    /// Redraws the dashboard in place on a terminal, or prints it as a single line otherwise
+    ///
+    /// It goes to stderr, so the log on stdout can be redirected away from the dashboard.
+    pub fn render(&self) {
+        let lines = self.lines();
+        let mut stderr = std::io::stderr();
+        if atty::is(atty::Stream::Stderr) {
+            // Clear the screen and move the cursor home
+            let _ = writeln!(stderr, "\x1b[2J\x1b[H{}", lines.join("\n"));
+        } else {
+            let _ = writeln!(stderr, "{}", lines.iter().map(|l| l.trim()).collect::<Vec<_>>().join(" | "));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AccountSyncSummary;
+
+    #[test]
+    fn accumulates_over_passes() {
+        let mut summary = SyncSummary::default();
+        summary.accounts.insert("a".to_string(), AccountSyncSummary { transactions_processed: 2, ..Default::default() });
+        let mut stats = BridgeStats { transactions_submitted: 2, chain_version: 120, last_verified_version: 100, ..Default::default() };
+        let mut sent_sequence = BTreeMap::new();
+        sent_sequence.insert("a".to_string(), 5);
+
+        let mut dashboard = Dashboard::default();
+        dashboard.update(&summary, &stats, &sent_sequence);
+        summary.accounts.get_mut("a").unwrap().error = Some("DeadlineExceeded".to_string());
+        stats.transactions_submitted = 4;
+        stats.rpc_errors = 1;
+        dashboard.update(&summary, &stats, &sent_sequence);
+
+        assert_eq!(dashboard.lines(), vec![
+            "pass 2  latest chain version 120".to_string(),
+            "verified version 100  lag 0 (avg 0.0)".to_string(),
+            "submitted this session: 4  rpc errors: 1".to_string(),
+            "  a  sent seq 5".to_string(),
+            "last error: a: DeadlineExceeded".to_string(),
+        ]);
+    }
+}
@@ -0,0 +1,71 @@
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use crate::error::Error;
+
+pub const DEFAULT_CALL_TIMEOUT_SECS: u64 = 20;
+
+/// Point in time a whole operation must be done by, shared by every network call it makes
+///
+/// Each call gets the time left, capped at its own timeout, so timeouts don't add up across
+/// the calls of the operation. The blocking Diem rpc client can't be interrupted from outside:
+/// its calls take `call_timeout` as the timeout of the http request itself, while `run` is only
+/// for async calls.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Deadline {
+    at: Option<Instant>,
+}
+
+impl Deadline {
+    /// A deadline that never passes, calls only get their own timeout
+    pub fn none() -> Self {
+        Deadline { at: None }
+    }
+
+    pub fn after(duration: Duration) -> Self {
+        Deadline { at: Some(Instant::now() + duration) }
+    }
+
+    /// Timeout for the next call, failing once the deadline has passed
+    pub fn call_timeout(&self, per_call: Duration) -> Result<Duration, Error> {
+        match self.at {
+            None => Ok(per_call),
+            Some(at) => {
+                let now = Instant::now();
+                if now >= at {
+                    return Err(Error::DeadlineExceeded);
+                }
+                Ok((at - now).min(per_call))
+            }
+        }
+    }
+
+    /// Runs `call` within `call_timeout(per_call)`
+    ///
+    /// `call` is only interrupted at an await point, a blocking call inside it runs to its end.
+    pub async fn run<T, F>(&self, per_call: Duration, call: F) -> Result<T, Error>
+        where F: Future<Output = Result<T, Error>> {
+        let timeout = self.call_timeout(per_call)?;
+        tokio::time::timeout(timeout, call).await.unwrap_or(Err(Error::DeadlineExceeded))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn calls_share_the_deadline() {
+        let per_call = Duration::from_secs(5);
+        assert_eq!(Deadline::none().call_timeout(per_call).unwrap(), per_call);
+        assert!(Deadline::after(Duration::from_secs(1)).call_timeout(per_call).unwrap() <= Duration::from_secs(1));
+
+        let deadline = Deadline::after(Duration::from_millis(20));
+        let slow = async {
+            tokio::time::delay_for(Duration::from_secs(1)).await;
+            Ok(())
+        };
+        assert!(matches!(deadline.run(per_call, slow).await, Err(Error::DeadlineExceeded)));
+        assert!(matches!(deadline.call_timeout(per_call), Err(Error::DeadlineExceeded)));
+    }
+}
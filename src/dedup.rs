@@ -0,0 +1,61 @@
+use std::collections::{HashSet, VecDeque};
+
+/// Smallest accepted dedup window, anything below would re-process on minor reorgs
+pub const MIN_DEDUP_WINDOW: usize = 16;
+pub const DEFAULT_DEDUP_WINDOW: usize = 1000;
+
+/// The most recently handled transaction versions of an account
+///
+/// The cursors decide where the next fetch starts; this window additionally catches duplicate
+/// versions near the tip (duplicate rpc responses, reorgs) after the cursor moved past them.
+/// A larger window catches older duplicates at the cost of memory.
+#[derive(Debug, Clone)]
+pub struct DedupWindow {
+    capacity: usize,
+    versions: HashSet<u64>,
+    order: VecDeque<u64>,
+}
+
+impl DedupWindow {
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(MIN_DEDUP_WINDOW);
+        DedupWindow {
+            capacity,
+            versions: HashSet::with_capacity(capacity),
+            order: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    pub fn contains(&self, version: u64) -> bool {
+        self.versions.contains(&version)
+    }
+
+    /// Records `version`, evicting the oldest one when the window is full
+    pub fn insert(&mut self, version: u64) {
+        if !self.versions.insert(version) {
+            return;
+        }
+        self.order.push_back(version);
+        if self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.versions.remove(&oldest);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evicts_oldest_beyond_capacity() {
+        let mut window = DedupWindow::new(0);
+        for version in 0..MIN_DEDUP_WINDOW as u64 + 1 {
+            window.insert(version);
+        }
+        assert!(!window.contains(0));
+        assert!(window.contains(1));
+        assert!(window.contains(MIN_DEDUP_WINDOW as u64));
+    }
+}
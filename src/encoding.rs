@@ -0,0 +1,87 @@
+use serde::{Serialize, de::DeserializeOwned};
+use diem_json_rpc_client::views::BytesView;
+use diem_types::event::EventKey;
+
+use crate::error::Error;
+
+/// BCS-serializes `value` and encodes the bytes as base64, the format pRuntime commands expect
+pub fn encode_b64_bcs<T: Serialize>(value: &T) -> Result<String, Error> {
+    let bytes = bcs::to_bytes(value).map_err(|_| Error::FailedToEncode)?;
+    Ok(base64::encode(&bytes))
+}
+
+/// Decodes a base64 string and BCS-deserializes the bytes into `T`
+pub fn decode_bcs_b64<T: DeserializeOwned>(data: &str) -> Result<T, Error> {
+    let bytes = base64::decode(data).map_err(|_| Error::FailedToDecode)?;
+    bcs::from_bytes(&bytes).map_err(|_| Error::FailedToDecode)
+}
+
+/// Default upper bound for a single proof field decoded from a Diem rpc response
+pub const DEFAULT_MAX_PROOF_BYTES: usize = 16 * 1024 * 1024;
+
+/// Decodes a hex `BytesView` from a Diem rpc response and BCS-deserializes it into `T`
+///
+/// Fields larger than `max_size` bytes are rejected before being decoded, so a hostile endpoint
+/// can't make the bridge allocate arbitrarily large buffers. Bytes that aren't hex nor valid BCS
+/// of `T` are `Error::MalformedProofData`: a sound endpoint never serves them.
+pub fn decode_bcs_hex<T: DeserializeOwned>(
+    view: BytesView,
+    field: &'static str,
+    max_size: usize,
+) -> Result<T, Error> {
+    let size = view.0.len() / 2;
+    if size > max_size {
+        return Err(Error::ProofTooLarge { field, size });
+    }
+    let bytes = view.into_bytes().map_err(|_| Error::MalformedProofData { field })?;
+    bcs::from_bytes(&bytes).map_err(|_| Error::MalformedProofData { field })
+}
+
+/// Checks that an event key from a Diem rpc response is hex of exactly `EventKey::LENGTH` bytes
+///
+/// Returns the key as canonical lowercase hex, the form the contract parses.
+pub fn validate_event_key(view: &BytesView, field: &'static str) -> Result<String, Error> {
+    match view.clone().into_bytes() {
+        Ok(bytes) if bytes.len() == EventKey::LENGTH => Ok(hex::encode(bytes)),
+        _ => Err(Error::InvalidEventKey(format!("{}: {:?}", field, view.0))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_bcs_hex_rejects_oversized_field() {
+        let view = BytesView::from(bcs::to_bytes(&vec![7u8; 64]).unwrap());
+        let decoded: Vec<u8> = decode_bcs_hex(view.clone(), "blob", 1024).unwrap();
+        assert_eq!(decoded, vec![7u8; 64]);
+
+        let result: Result<Vec<u8>, Error> = decode_bcs_hex(view, "blob", 32);
+        assert!(matches!(result, Err(Error::ProofTooLarge { field: "blob", size: 65 })));
+    }
+
+    #[test]
+    fn decode_bcs_hex_reports_malformed_field() {
+        let result: Result<Vec<u8>, Error> = decode_bcs_hex(BytesView("zz".to_string()), "blob", 1024);
+        assert!(matches!(result, Err(Error::MalformedProofData { field: "blob" })));
+        let result: Result<Vec<u8>, Error> = decode_bcs_hex(BytesView("ffffff".to_string()), "blob", 1024);
+        assert!(matches!(result, Err(Error::MalformedProofData { field: "blob" })));
+    }
+
+    #[test]
+    fn validate_event_key_checks_length_and_hex() {
+        let key = BytesView::from(vec![1u8; EventKey::LENGTH]);
+        assert_eq!(validate_event_key(&key, "sent_events_key").unwrap(), key.0);
+
+        let short = BytesView::from(vec![1u8; EventKey::LENGTH - 1]);
+        assert!(matches!(validate_event_key(&short, "sent_events_key"), Err(Error::InvalidEventKey(_))));
+        let empty = BytesView::from(vec![]);
+        assert!(matches!(validate_event_key(&empty, "sent_events_key"), Err(Error::InvalidEventKey(_))));
+        let upper = BytesView("AB".repeat(EventKey::LENGTH));
+        assert_eq!(validate_event_key(&upper, "sent_events_key").unwrap(), "ab".repeat(EventKey::LENGTH));
+
+        let not_hex = BytesView("zz".repeat(EventKey::LENGTH));
+        assert!(matches!(validate_event_key(&not_hex, "sent_events_key"), Err(Error::InvalidEventKey(_))));
+    }
+}
@@ -5,18 +5,66 @@ pub enum Error {
     UriError(hyper::http::uri::InvalidUri),
     SubxtRpcError(subxt::Error),
     SerdeError(serde_json::error::Error),
+    IoError(std::io::Error),
+    FailedToEncode,
     FailedToDecode,
     BadTransactionHash,
     FailedToGetResponse,
+    MissingRpcResponse,
     FailedToGetTransaction,
     NoTransaction,
     FailedToInitState,
+    StateNotInitialized,
+    StaleStateProof { version: u64, known_version: u64 },
+    InvalidStateProof(String),
+    StateProofSignatureFailure(String),
+    StaleLedgerInfo { age: u64 },
+    ProofTooLarge { field: &'static str, size: usize },
+    MalformedProofData { field: &'static str },
+    AccountMismatch { expected: String, actual: String },
+    ChainIdMismatch { expected: u8, actual: u8 },
+    InvalidConfig(String),
+    InvalidSnapshot(String),
+    InvalidEventKey(String),
+    DeadlineExceeded,
+    StrictVerificationFailure(String),
+    QuorumNotReached { successes: usize, quorum: usize },
+    AuthKeyAddressMismatch { address: String, authentication_key: String },
+    UnexpectedContentType(String),
+    VersionPruned,
+    WaypointMismatch { expected: String, actual: String },
+    UnknownCurrency(String),
+    ResponseTooLarge { limit: usize },
+    NoMatchingWaypoint { epoch: u64 },
+    InconsistentLedgerState { li_epoch: u64, trusted_epoch: u64 },
+    BalanceMismatch { account: String, version: u64 },
     FailedToCallPushCommand,
     FailedToGetReceivingTransactions,
     FailedToGetSentTransactions,
     FailedToSubmitTransaction,
 }
 
+impl Error {
+    /// Whether retrying the same operation later may succeed
+    ///
+    /// Errors caused by the data itself (bad hashes, oversized proofs) are permanent, everything
+    /// else (network, rpc, chain submission) is considered transient. Proof bytes that don't even
+    /// decode point at the endpoint rather than the transaction, so `MalformedProofData` is
+    /// transient as well.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Error::FailedToEncode
+            | Error::FailedToDecode
+            | Error::BadTransactionHash
+            | Error::NoTransaction
+            | Error::ProofTooLarge { .. }
+            | Error::StrictVerificationFailure(_)
+            | Error::VersionPruned => false,
+            _ => true,
+        }
+    }
+}
+
 impl From<hyper::error::Error> for Error {
     fn from(error: hyper::error::Error) -> Error {
         Error::HyperError(error)
@@ -41,6 +89,12 @@ impl From<serde_json::error::Error> for Error {
     }
 }
 
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Error {
+        Error::IoError(error)
+    }
+}
+
 impl From<subxt::Error> for Error {
     fn from(error: subxt::Error) -> Error {
         Error::SubxtRpcError(error)
@@ -0,0 +1,174 @@
+//! Append-only export of the verified transactions, for re-verification or analysis
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::str::FromStr;
+
+use diem_json_rpc_client::views::{ScriptView, TransactionDataView, TransactionView};
+use serde_json::json;
+
+use crate::dedup::DedupWindow;
+use crate::error::Error;
+use crate::TransactionWithProof;
+
+/// Layout of the export file
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    /// One json object per line, the proof as hex encoded BCS
+    Jsonl,
+    /// Records of a 4 byte little endian length followed by the BCS encoding of
+    /// `(account_address, TransactionWithProof)`, for exact re-verification
+    Bcs,
+    /// `version,account,sender,type,amount,currency,vm_status,gas_used,events` lines, amount and
+    /// currency empty for other than payment and mint scripts, events the number emitted
+    Csv,
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "jsonl" => Ok(ExportFormat::Jsonl),
+            "bcs" => Ok(ExportFormat::Bcs),
+            "csv" => Ok(ExportFormat::Csv),
+            _ => Err(format!("unknown export format: {}, expected jsonl, bcs or csv", s)),
+        }
+    }
+}
+
+const CSV_HEADER: &str = "version,account,sender,type,amount,currency,vm_status,gas_used,events";
+
+/// Quotes a csv field holding a separator, quote or line break
+fn csv_field(value: &str) -> Cow<str> {
+    if value.contains(&[',', '"', '\n', '\r'][..]) {
+        Cow::Owned(format!("\"{}\"", value.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(value)
+    }
+}
+
+/// A verified transaction as written to the export file, in any format
+pub struct ExportRecord<'a> {
+    pub account_address: &'a str,
+    pub transaction: &'a TransactionView,
+    pub proof: &'a TransactionWithProof,
+}
+
+impl<'a> ExportRecord<'a> {
+    fn sender(&self) -> String {
+        match &self.transaction.transaction {
+            TransactionDataView::UserTransaction { sender, .. } => sender.0.clone(),
+            _ => String::new(),
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        match &self.transaction.transaction {
+            TransactionDataView::UserTransaction { script: ScriptView::PeerToPeer { .. }, .. } => "peer_to_peer",
+            TransactionDataView::UserTransaction { script: ScriptView::Mint { .. }, .. } => "mint",
+            TransactionDataView::UserTransaction { .. } => "user",
+            TransactionDataView::BlockMetadata { .. } => "block_metadata",
+            TransactionDataView::WriteSet { .. } => "write_set",
+            TransactionDataView::UnknownTransaction { .. } => "unknown",
+        }
+    }
+
+    /// The `type` tag of the vm status, `executed` for a successful transaction
+    fn vm_status(&self) -> String {
+        serde_json::to_value(&self.transaction.vm_status).ok()
+            .and_then(|status| status["type"].as_str().map(str::to_string))
+            .unwrap_or_default()
+    }
+
+    fn amount(&self) -> Option<(u64, &str)> {
+        match &self.transaction.transaction {
+            TransactionDataView::UserTransaction { script: ScriptView::PeerToPeer { amount, currency, .. }, .. }
+            | TransactionDataView::UserTransaction { script: ScriptView::Mint { amount, currency, .. }, .. } => {
+                Some((*amount, currency.as_str()))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn encode(&self, format: ExportFormat) -> Result<Vec<u8>, Error> {
+        match format {
+            ExportFormat::Jsonl => {
+                let proof = bcs::to_bytes(self.proof).map_err(|_| Error::FailedToEncode)?;
+                let line = json!({
+                    "version": self.transaction.version,
+                    "account": self.account_address,
+                    "sender": self.sender(),
+                    "type": self.kind(),
+                    "amount": self.amount().map(|(amount, _)| amount),
+                    "currency": self.amount().map(|(_, currency)| currency),
+                    "vm_status": self.transaction.vm_status,
+                    "gas_used": self.transaction.gas_used,
+                    "events": self.transaction.events,
+                    "transaction_with_proof": hex::encode(proof),
+                });
+                Ok(format!("{}\n", line).into_bytes())
+            }
+            ExportFormat::Bcs => {
+                let record = bcs::to_bytes(&(self.account_address, self.proof)).map_err(|_| Error::FailedToEncode)?;
+                let mut data = (record.len() as u32).to_le_bytes().to_vec();
+                data.extend_from_slice(&record);
+                Ok(data)
+            }
+            ExportFormat::Csv => {
+                let (amount, currency) = self.amount()
+                    .map_or((String::new(), String::new()), |(amount, currency)| (amount.to_string(), currency.to_string()));
+                let fields = [
+                    self.transaction.version.to_string(),
+                    self.account_address.to_string(),
+                    self.sender(),
+                    self.kind().to_string(),
+                    amount,
+                    currency,
+                    self.vm_status(),
+                    self.transaction.gas_used.to_string(),
+                    self.transaction.events.len().to_string(),
+                ];
+                let line: Vec<_> = fields.iter().map(|field| csv_field(field)).collect();
+                Ok(format!("{}\n", line.join(",")).into_bytes())
+            }
+        }
+    }
+}
+
+/// Appends records to the export file
+pub struct Exporter {
+    format: ExportFormat,
+    file: File,
+    /// The latest versions written per account, a transaction resubmitted after a failure is only
+    /// exported once
+    exported: HashMap<String, DedupWindow>,
+    dedup_window: usize,
+}
+
+impl Exporter {
+    /// Opens `path` for appending, remembering the last `dedup_window` versions exported per
+    /// account
+    pub fn open(path: &str, format: ExportFormat, dedup_window: usize) -> Result<Self, Error> {
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        if format == ExportFormat::Csv && file.metadata()?.len() == 0 {
+            writeln!(file, "{}", CSV_HEADER)?;
+        }
+        Ok(Exporter { format, file, exported: HashMap::new(), dedup_window })
+    }
+
+    pub fn write(&mut self, record: &ExportRecord) -> Result<(), Error> {
+        let dedup_window = self.dedup_window;
+        let exported = self.exported.entry(record.account_address.to_string())
+            .or_insert_with(|| DedupWindow::new(dedup_window));
+        if exported.contains(record.transaction.version) {
+            return Ok(());
+        }
+        exported.insert(record.transaction.version);
+        let data = record.encode(self.format)?;
+        self.file.write_all(&data)?;
+        Ok(())
+    }
+}
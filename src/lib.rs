@@ -0,0 +1,4430 @@
+use structopt::StructOpt;
+use std::collections::{BTreeMap, BTreeSet};
+
+use diem_client::{
+    AccountData,
+    AccountStatus,
+};
+use anyhow::{ensure, Result};
+use reqwest::Url;
+use diem_crypto::hash::CryptoHash;
+
+use diem_types::{
+    account_address::{
+        AccountAddress, HashAccountAddress
+    },
+    chain_id::{ChainId, NamedChain},
+    ledger_info::LedgerInfoWithSignatures,
+    account_config::{AccountResource, from_currency_code_string},
+    account_state::AccountState,
+    transaction::{Transaction, TransactionInfo, SignedTransaction},
+    epoch_change::EpochChangeProof,
+    epoch_state::EpochState,
+    proof::{
+        AccountStateProof,
+        TransactionInfoWithProof,
+        TransactionAccumulatorProof,
+    },
+    trusted_state::{TrustedState, TrustedStateChange},
+    validator_verifier::VerifyError,
+    waypoint::Waypoint,
+};
+use diem_json_rpc_client::{
+    get_response_from_batch, process_batch_response,
+    errors::JsonRpcError,
+    views::{
+        AccountStateWithProofView, AccountView, BlockMetadata, BytesView,
+        EventView, StateProofView, TransactionView, TransactionDataView, VMStatusView,
+    },
+    ConnectionOptions, JsonRpcBatch, JsonRpcClient, ResponseAsView, JsonRpcResponse,
+};
+use std::{convert::TryFrom, str::FromStr};
+use diem_types::account_state_blob::AccountStateBlob;
+type SparseMerkleProof = diem_types::proof::SparseMerkleProof<AccountStateBlob>;
+
+mod pruntime_client;
+mod pruntime_pool;
+mod types;
+mod error;
+mod runtimes;
+mod encoding;
+mod state_file;
+mod watchdog;
+mod clock;
+mod snapshot;
+mod dedup;
+mod tx_cache;
+mod metrics;
+mod verification;
+mod retry;
+mod deadline;
+mod cassette;
+mod currency;
+mod dashboard;
+mod export;
+mod publish;
+mod accounts;
+mod balances;
+mod contract;
+#[cfg(test)]
+mod mock_rpc;
+
+use std::cmp;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use crate::types::{Runtime, Payload, QueryReqData, QueryRespData, TransactionData};
+use sp_core::{sr25519, crypto::Pair};
+pub use crate::contract::{CommandSink, ChainSink, SrSigner, XtClient};
+
+type PrClient = pruntime_client::PRuntimeClient;
+pub use crate::pruntime_client::{PRuntime, PRuntimeClient, BodyEncoding};
+pub use crate::pruntime_pool::{PRuntimePool, PoolMode, WorkerStats};
+
+const DIEM_CONTRACT_ID: u32 = 5;
+const RECEIVING_EVENTS_LIMIT: u64 = 100;
+const DEFAULT_FETCH_WINDOW: u64 = 100;
+/// The most transactions the Diem json-rpc returns for one get_account_transactions call
+const ACCOUNT_TRANSACTIONS_PAGE_SIZE: u64 = 1000;
+/// Times a pass resyncs an account whose sequence number advanced while it was synced
+const MAX_ACCOUNT_REFETCHES: u32 = 2;
+
+pub use crate::error::Error;
+use crate::types::{CommandReqData};
+use crate::encoding::{encode_b64_bcs, decode_bcs_hex, validate_event_key, DEFAULT_MAX_PROOF_BYTES};
+use crate::state_file::{SyncCursors, write_atomic};
+use crate::watchdog::Watchdog;
+use crate::clock::{Clock, SystemClock};
+use crate::snapshot::BridgeSnapshot;
+use crate::dedup::{DedupWindow, DEFAULT_DEDUP_WINDOW, MIN_DEDUP_WINDOW};
+use crate::tx_cache::{TransactionCache, DEFAULT_TX_CACHE_SIZE};
+use crate::deadline::{Deadline, DEFAULT_CALL_TIMEOUT_SECS};
+use crate::cassette::Cassette;
+use crate::currency::normalize_currency;
+use crate::dashboard::Dashboard;
+use crate::export::{ExportFormat, ExportRecord, Exporter};
+use crate::publish::Publisher;
+use crate::accounts::{read_accounts_file, diff_accounts};
+use crate::balances::{balance_delta, BalanceLedger};
+use crate::metrics::{LatencyHistogram, Ema, DEFAULT_LAG_EMA_ALPHA};
+use crate::verification::{VerificationIssue, VerificationReport};
+use crate::retry::{
+    RetryPolicy, DefaultRetryPolicy, RpcFailure, RetryBudget, is_pruned_error,
+    DEFAULT_RPC_RETRIES, DEFAULT_RETRY_BUDGET, DEFAULT_RETRY_BUDGET_REFILL_PER_SEC,
+};
+
+use serde::{Serialize, Deserialize};
+use codec::Decode;
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "pDiem")]
+pub struct Args {
+    #[structopt(
+    default_value = "http://127.0.0.1:8080", long,
+    help = "Diem rpc endpoint")]
+    diem_rpc_endpoint: String, //official rpc endpoint: https://testnet.diem.com
+
+    #[structopt(
+    default_value = "http://127.0.0.1:8000", long, number_of_values = 1,
+    help = "pRuntime http endpoint, can be repeated for several workers hosting the same contract")]
+    pruntime_endpoint: Vec<String>,
+
+    #[structopt(default_value = "roundrobin", long,
+    help = "How queries are spread over the pRuntime workers: fanout or roundrobin")]
+    pruntime_mode: PoolMode,
+
+    #[structopt(default_value = "1", long,
+    help = "Number of pRuntime workers that must answer a query in fanout mode")]
+    pruntime_quorum: usize,
+
+    #[structopt(long, parse(try_from_str = parse_chain_id_setting),
+    help = "Diem chain id (name or number), or `auto` to adopt the id reported by the rpc endpoint")]
+    chain_id: Option<ChainIdSetting>,
+
+    #[structopt(long,
+    help = "Gzip request bodies sent to pRuntime")]
+    pruntime_compress: bool,
+
+    #[structopt(long, number_of_values = 1, parse(try_from_str = parse_pruntime_header),
+    help = "Header attached to every pRuntime request as KEY=VALUE, can be repeated")]
+    pruntime_header: Vec<(String, String)>,
+
+    #[structopt(default_value = "json", long,
+    help = "Encoding of request bodies sent to pRuntime: json, or bcs if the pRuntime accepts it")]
+    pruntime_encoding: BodyEncoding,
+
+    #[structopt(default_value = "16777216", long,
+    help = "Largest pRuntime response body accepted, in bytes")]
+    pruntime_max_response_bytes: usize,
+
+    #[structopt(
+    required = true,
+    default_value = "//Alice",
+    short = "m", long = "mnemonic",
+    help = "Controller SR25519 private key mnemonic, private key seed, or derive path")]
+    mnemonic: String,
+
+    #[structopt(
+    default_value = "ws://localhost:9944", long,
+    help = "Substrate rpc websocket endpoint")]
+    substrate_ws_endpoint: String,
+
+    #[structopt(default_value = "15", long,
+    help = "The interval in seconds.")]
+    interval: u64,
+
+    #[structopt(long,
+    help = "File to persist the per-account sync cursors to, and to resume them from on start")]
+    state_file: Option<String>,
+
+    #[structopt(long,
+    help = "Trust the cursors in --state-file and skip re-verifying transactions at or below them")]
+    verify_only_new: bool,
+
+    #[structopt(long,
+    help = "Also log non-user transactions (block metadata, write set, unknown) while syncing")]
+    include_system_txns: bool,
+
+    #[structopt(long,
+    help = "Reject ledger infos older than this many seconds (disabled by default, beware of clock skew)")]
+    max_ledger_info_age_secs: Option<u64>,
+
+    #[structopt(default_value = "16777216", long,
+    help = "Maximum size in bytes of a single proof field accepted from the Diem rpc endpoint")]
+    max_proof_bytes: usize,
+
+    #[structopt(long,
+    help = "Abort the sync pass on the first account that fails instead of continuing with the others")]
+    fail_fast: bool,
+
+    #[structopt(long,
+    help = "Discard the cursors in --state-file and re-verify the whole history")]
+    reset_cursor: bool,
+
+    #[structopt(long,
+    help = "Confirm --reset-cursor, re-submitting transactions the contract already applied")]
+    confirm_reset: bool,
+
+    #[structopt(long,
+    help = "Log what would be submitted to the contract without submitting it")]
+    dry_run: bool,
+
+    #[structopt(default_value = "1000", long,
+    help = "Recent versions per account kept to catch duplicates near the tip (min 16); larger costs memory")]
+    dedup_window: usize,
+
+    #[structopt(default_value = "100", long,
+    help = "Number of transactions fetched per rpc call when looking up received transactions")]
+    fetch_window: u64,
+
+    #[structopt(default_value = "1000", long,
+    help = "Number of fetched transactions cached by version (at least --fetch-window)")]
+    tx_cache_size: usize,
+
+    #[structopt(long,
+    help = "Log the proof verification latency of every transaction and report it in the sync summary")]
+    log_verify_timing: bool,
+
+    #[structopt(long,
+    help = "Give up on an account after syncing it for this many seconds and move on (disabled by default)")]
+    sync_timeout_secs: Option<u64>,
+
+    #[structopt(long,
+    help = "Never submit a transaction whose proof verification reported any anomaly, and halt the bridge on it")]
+    strict: bool,
+
+    #[structopt(long,
+    help = "Don't ask the rpc for transaction events along with the transactions, saving bandwidth")]
+    no_inline_events: bool,
+
+    #[structopt(default_value = "2", long,
+    help = "Number of retries of a Diem rpc request failing with a retryable error")]
+    rpc_retries: u32,
+
+    #[structopt(default_value = "0.2", long,
+    help = "Smoothing factor in (0, 1] of the sync lag moving average; smaller reacts slower to spikes")]
+    lag_ema_alpha: f64,
+
+    #[structopt(long,
+    help = "Full history Diem rpc endpoint to get account states the main endpoint has pruned")]
+    archive_endpoint: Option<String>,
+
+    #[structopt(default_value = "20", long,
+    help = "Rpc retries allowed in a burst across the whole bridge, bounding retry storms on a failing endpoint")]
+    retry_budget: u32,
+
+    #[structopt(default_value = "1.0", long,
+    help = "Rpc retries added back to --retry-budget per second")]
+    retry_budget_refill: f64,
+
+    #[structopt(long,
+    help = "Submit the proofs of each page of sent transactions together; the contract must support VerifyTransactions")]
+    batch_proofs: bool,
+
+    #[structopt(default_value = "20", long,
+    help = "Timeout of a single Diem rpc or pRuntime call, capped by what's left of --sync-timeout-secs")]
+    call_timeout_secs: u64,
+
+    #[structopt(long,
+    help = "Don't submit transactions that failed on chain (aborted, out of gas...), only record them as skipped")]
+    skip_failed_txns: bool,
+
+    #[structopt(long,
+    help = "Append every Diem rpc request and raw response to this file, for --replay-rpc")]
+    record_rpc: Option<String>,
+
+    #[structopt(long,
+    help = "Serve Diem rpc requests from a file written with --record-rpc instead of the endpoints, which must match the recording")]
+    replay_rpc: Option<String>,
+
+    #[structopt(long,
+    help = "Append every verified transaction to this file once its proof is submitted")]
+    export_file: Option<String>,
+
+    #[structopt(default_value = "jsonl", long,
+    help = "Layout of --export-file: jsonl, bcs (length prefixed records) or csv")]
+    export_format: ExportFormat,
+
+    #[structopt(long = "waypoint", number_of_values = 1,
+    help = "Epoch changes must match one of these waypoints (<version>:<hash>), can be repeated")]
+    waypoints: Vec<Waypoint>,
+
+    #[structopt(long,
+    help = "Publish every verified transaction, once submitted, as a json line to readers of this Unix socket")]
+    publish_uds: Option<String>,
+
+    #[structopt(default_value = "0", long,
+    help = "Only process transactions at least this many versions behind the latest verified one, deferring the rest to a later pass. Guards against short reorgs at the cost of latency")]
+    confirmation_versions: u64,
+
+    #[structopt(long,
+    help = "File listing extra account addresses to monitor, one per line with # comments, reloaded on SIGHUP")]
+    accounts_file: Option<String>,
+
+    #[structopt(long,
+    help = "Cross-check account balances against the transactions synced since the previous pass, warning on divergence (failing the pass with --strict)")]
+    verify_balances: bool,
+
+    #[structopt(long,
+    help = "Take over the verified chain state and cursors of a snapshot written by --export-snapshot at startup, checked against --waypoint")]
+    import_snapshot: Option<String>,
+
+    #[structopt(long,
+    help = "Write the verified chain state and cursors to this file after every pass, for a standby bridge to import")]
+    export_snapshot: Option<String>,
+
+    #[structopt(long,
+    help = "Maximum idle connections kept open to the Diem rpc endpoint [default: no limit]")]
+    rpc_pool_max_idle: Option<usize>,
+
+    #[structopt(long,
+    help = "Talk HTTP/2 to the Diem rpc endpoint without negotiating it, the endpoint must support it")]
+    rpc_http2: bool,
+
+    #[structopt(long,
+    help = "Exit with a nonzero code if no account sync succeeded for this many seconds (disabled by default)")]
+    max_sync_lag_secs: Option<u64>,
+
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Verify the latest state proof, print the trusted validator set and exit
+    Validators,
+    /// Check a waypoint against the epoch changes served by the rpc endpoint and exit
+    CheckWaypoint {
+        #[structopt(long, help = "Waypoint to check, as <version>:<hash>")]
+        waypoint: Waypoint,
+    },
+    /// Run the bridge with a compact status dashboard on stderr, leaving the per transaction details
+    /// out of the log on stdout
+    Watch,
+    /// Wipe the cursors in --state-file, of one account or all, then re-bootstrap and sync again
+    /// from scratch. Transactions the contract already applied are submitted again
+    Resync {
+        #[structopt(long, help = "Only wipe the cursors of this account")]
+        account: Option<String>,
+        #[structopt(long, help = "Don't ask for confirmation")]
+        yes: bool,
+    },
+    /// Fetch and verify the proof of an account's state at a transaction, print it as base64 bcs
+    /// and exit, without submitting anything
+    Prove {
+        #[structopt(long, help = "Account whose state the transaction is proven into")]
+        account: String,
+        #[structopt(long, help = "Version of the transaction")]
+        version: u64,
+    },
+}
+
+/// How the bridge settles on the Diem chain id
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChainIdSetting {
+    /// Guessed from the rpc endpoint url and never checked
+    FromUrl,
+    /// Adopted from the first metadata response, then frozen
+    Auto,
+    /// Checked against every metadata response
+    Fixed(ChainId),
+}
+
+fn parse_pruntime_header(s: &str) -> Result<(String, String)> {
+    let (name, value) = match s.find('=') {
+        Some(pos) => (s[..pos].trim(), s[pos + 1..].trim()),
+        None => return Err(anyhow::anyhow!("expected KEY=VALUE, got {:?}", s)),
+    };
+    hyper::header::HeaderName::from_bytes(name.as_bytes())?;
+    hyper::header::HeaderValue::from_str(value)?;
+    Ok((name.to_string(), value.to_string()))
+}
+
+fn parse_chain_id_setting(s: &str) -> Result<ChainIdSetting> {
+    if s == "auto" {
+        Ok(ChainIdSetting::Auto)
+    } else {
+        Ok(ChainIdSetting::Fixed(ChainId::from_str(s)?))
+    }
+}
+
+/// Outcome of refetching an account at the end of its sync, see `DiemBridge::account_advanced`
+enum Refetch {
+    /// No transactions since the pinned sequence number
+    Current,
+    /// Sent more transactions, provable against the ratcheted trusted state
+    Advanced(AccountView),
+    /// Sent more transactions, but the chain moved to a new epoch meanwhile
+    EpochChanged,
+}
+
+/// An rpc client with the url it talks to, which keys its exchanges in a cassette
+struct RpcEndpoint {
+    url: String,
+    client: JsonRpcClient,
+}
+
+pub struct DiemBridge {
+    chain_id: ChainId,
+    chain_id_setting: ChainIdSetting,
+    rpc: RpcEndpoint,
+    archive: Option<RpcEndpoint>,
+    cassette: Option<Cassette>,
+    exporter: Option<Exporter>,
+    publisher: Option<Publisher>,
+    confirmation_versions: u64,
+    verify_balances: bool,
+    balance_ledgers: BTreeMap<String, BalanceLedger>,
+    stats: BridgeStats,
+    /// Counted apart from `stats`, rpc calls only borrow the bridge
+    rpc_errors: AtomicU64,
+    chain_version: AtomicU64,
+    waypoints: Vec<Waypoint>,
+    epoch_change_proof: Option<EpochChangeProof>,
+    trusted_state: Option<TrustedState>,
+    latest_epoch_change_li: Option<LedgerInfoWithSignatures>,
+    latest_li: Option<LedgerInfoWithSignatures>,
+    received_versions: BTreeMap<String, DedupWindow>,
+    sent_versions: BTreeMap<String, DedupWindow>,
+    dedup_window: usize,
+    tx_cache: TransactionCache,
+    fetch_window: u64,
+    account: BTreeMap<String, AccountData>,
+    cursors: SyncCursors,
+    state_file: Option<String>,
+    verify_only_new: bool,
+    include_system_txns: bool,
+    transaction_counters: TransactionCounters,
+    summary: SyncSummary,
+    max_ledger_info_age_secs: Option<u64>,
+    clock: Arc<dyn Clock>,
+    max_proof_bytes: usize,
+    fail_fast: bool,
+    account_failures: BTreeMap<String, u64>,
+    reset_cursors: Option<SyncCursors>,
+    dry_run: bool,
+    quiet: bool,
+    log_verify_timing: bool,
+    sync_timeout_secs: Option<u64>,
+    strict: bool,
+    include_events: bool,
+    retry_policy: Arc<dyn RetryPolicy>,
+    rpc_retries: u32,
+    retry_budget: Mutex<RetryBudget>,
+    batch_proofs: bool,
+    skip_failed_txns: bool,
+    call_timeout: Duration,
+    /// Deadline of the account being synced, `Deadline::none()` between accounts
+    deadline: Deadline,
+    lag_ema: Ema,
+    /// Versions discovered this pass but not submitted nor skipped yet, per account
+    pending: BTreeMap<String, BTreeSet<u64>>,
+    /// Versions submitted or skipped this pass per account, shared by the sent and received paths
+    /// so a self transfer is only submitted once
+    handled: BTreeMap<String, BTreeSet<u64>>,
+    /// Highest ledger version submitted or skipped, or confirmed with nothing left to sync by a
+    /// pass without failures
+    processed_version: Option<u64>,
+    /// Epoch of the trusted state last submitted with `SetTrustedState`, `None` before the first
+    anchored_epoch: Option<u64>,
+    /// Accounts already warned about for an authentication key not matching their address
+    rotated_keys: BTreeSet<String>,
+    address: Vec<String>,
+}
+
+/// Number of synced transactions seen per `TransactionDataView` variant
+#[derive(Debug, Clone, Default)]
+pub struct TransactionCounters {
+    pub user: u64,
+    pub block_metadata: u64,
+    pub write_set: u64,
+    pub unknown: u64,
+}
+
+impl TransactionCounters {
+    fn record(&mut self, transaction: &TransactionDataView) {
+        match transaction {
+            TransactionDataView::UserTransaction {..} => self.user += 1,
+            TransactionDataView::BlockMetadata {..} => self.block_metadata += 1,
+            TransactionDataView::WriteSet {..} => self.write_set += 1,
+            TransactionDataView::UnknownTransaction {..} => self.unknown += 1,
+        }
+    }
+}
+
+/// Outcome of syncing a single account in one pass
+#[derive(Debug, Clone, Default)]
+pub struct AccountSyncSummary {
+    pub transactions_processed: u64,
+    pub transactions_skipped: u64,
+    /// Why each skipped transaction was skipped, as `(version, reason)`
+    pub skip_reasons: Vec<(u64, String)>,
+    pub events_seen: u64,
+    /// The rpc answered that the account doesn't exist, which isn't counted as a failure
+    pub account_missing: bool,
+    pub error: Option<String>,
+    /// Failed passes for this account since the bridge started
+    pub total_failures: u64,
+    /// Versions discovered this pass but neither submitted nor skipped, left to the next pass
+    pub pending_versions: Vec<u64>,
+}
+
+/// Counters of the bridge over its whole run, see `DiemBridge::stats_snapshot`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BridgeStats {
+    /// Transactions submitted to the contract, one per version of a batched submission
+    pub transactions_submitted: u64,
+    pub events_processed: u64,
+    /// Failed rpc attempts, retried or not
+    pub rpc_errors: u64,
+    /// Latest ledger version reported by the rpc endpoint
+    pub chain_version: u64,
+    pub last_verified_version: u64,
+    /// Clock time in seconds of the end of the last successful pass
+    pub last_success_secs: Option<u64>,
+}
+
+/// Outcome of one `DiemBridge::sync_once` pass
+#[derive(Debug, Clone, Default)]
+pub struct SyncSummary {
+    pub accounts: BTreeMap<String, AccountSyncSummary>,
+    pub epoch_changes: u64,
+    pub verified_version: u64,
+    /// Proof verification latency, only recorded with `log_verify_timing`
+    pub verify_latency: LatencyHistogram,
+    /// Reports of the transactions verified with anomalies
+    pub verification_reports: Vec<VerificationReport>,
+    /// Versions the trusted tip is ahead of the highest version processed, see `record_sync_lag`
+    pub sync_lag: u64,
+    /// Moving average of `sync_lag` over the passes, see `DiemBridgeBuilder::lag_ema_alpha`
+    pub sync_lag_ema: f64,
+    /// Counters of each pRuntime worker since the bridge started
+    pub worker_stats: Vec<WorkerStats>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct Amount {
+    pub amount: u64,
+    pub currency: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccountInfo {
+    pub address: AccountAddress,
+    pub authentication_key: Option<Vec<u8>>,
+    pub sequence_number: u64,
+    pub sent_events_key: String,
+    pub received_events_key: String,
+    pub balances: Vec<Amount>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransactionWithProof {
+    transaction_bytes: Vec<u8>,
+
+    epoch_change_proof: EpochChangeProof,
+    ledger_info_with_signatures: LedgerInfoWithSignatures,
+
+    ledger_info_to_transaction_info_proof: TransactionAccumulatorProof,
+    transaction_info: TransactionInfo,
+    transaction_info_to_account_proof: SparseMerkleProof,
+    account_state_blob: AccountStateBlob,
+
+    version: u64,
+
+    /// Events emitted by the transaction, empty when the rpc returned none
+    ///
+    /// Kept out of the BCS encoding submitted to the contract, which verifies the transaction
+    /// itself and doesn't read them.
+    #[serde(skip)]
+    events: Vec<EventView>,
+    /// Execution outcome reported by the rpc, kept out of the BCS encoding like `events`
+    #[serde(skip)]
+    vm_status: Option<VMStatusView>,
+    #[serde(skip)]
+    gas_used: u64,
+}
+
+impl TransactionWithProof {
+    pub fn events(&self) -> &[EventView] {
+        &self.events
+    }
+
+    pub fn vm_status(&self) -> Option<&VMStatusView> {
+        self.vm_status.as_ref()
+    }
+
+    pub fn gas_used(&self) -> u64 {
+        self.gas_used
+    }
+}
+
+/// The per transaction part of a `TransactionWithProof`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransactionProof {
+    transaction_bytes: Vec<u8>,
+
+    ledger_info_to_transaction_info_proof: TransactionAccumulatorProof,
+    transaction_info: TransactionInfo,
+    transaction_info_to_account_proof: SparseMerkleProof,
+    account_state_blob: AccountStateBlob,
+
+    version: u64,
+}
+
+/// Several transactions proven against the same ledger info, carrying the ledger info and the
+/// epoch change proof once instead of once per transaction
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransactionsWithProof {
+    epoch_change_proof: EpochChangeProof,
+    ledger_info_with_signatures: LedgerInfoWithSignatures,
+
+    transactions: Vec<TransactionProof>,
+}
+
+impl TransactionsWithProof {
+    /// Groups consecutive proofs sharing their ledger info and epoch change proof, keeping order
+    pub fn group(proofs: Vec<TransactionWithProof>) -> Vec<TransactionsWithProof> {
+        let mut groups: Vec<TransactionsWithProof> = Vec::new();
+        for proof in proofs {
+            let transaction = TransactionProof {
+                transaction_bytes: proof.transaction_bytes,
+                ledger_info_to_transaction_info_proof: proof.ledger_info_to_transaction_info_proof,
+                transaction_info: proof.transaction_info,
+                transaction_info_to_account_proof: proof.transaction_info_to_account_proof,
+                account_state_blob: proof.account_state_blob,
+                version: proof.version,
+            };
+            match groups.last_mut() {
+                Some(group) if group.ledger_info_with_signatures == proof.ledger_info_with_signatures
+                    && group.epoch_change_proof == proof.epoch_change_proof => group.transactions.push(transaction),
+                _ => groups.push(TransactionsWithProof {
+                    epoch_change_proof: proof.epoch_change_proof,
+                    ledger_info_with_signatures: proof.ledger_info_with_signatures,
+                    transactions: vec![transaction],
+                }),
+            }
+        }
+        groups
+    }
+
+    pub fn versions(&self) -> Vec<u64> {
+        self.transactions.iter().map(|t| t.version).collect()
+    }
+}
+
+/// Assembles a `DiemBridge`, validating the combination of options before constructing it
+pub struct DiemBridgeBuilder {
+    rpc_endpoint: String,
+    chain_id_setting: ChainIdSetting,
+    state_file: Option<String>,
+    verify_only_new: bool,
+    include_system_txns: bool,
+    max_ledger_info_age_secs: Option<u64>,
+    clock: Arc<dyn Clock>,
+    max_proof_bytes: usize,
+    fail_fast: bool,
+    reset_cursor: bool,
+    confirm_reset: bool,
+    dry_run: bool,
+    quiet: bool,
+    dedup_window: usize,
+    fetch_window: u64,
+    tx_cache_size: usize,
+    log_verify_timing: bool,
+    sync_timeout_secs: Option<u64>,
+    strict: bool,
+    include_events: bool,
+    retry_policy: Arc<dyn RetryPolicy>,
+    rpc_retries: u32,
+    lag_ema_alpha: f64,
+    archive_endpoint: Option<String>,
+    retry_budget: u32,
+    retry_budget_refill_per_sec: f64,
+    batch_proofs: bool,
+    call_timeout_secs: u64,
+    skip_failed_txns: bool,
+    record_rpc: Option<String>,
+    replay_rpc: Option<String>,
+    export_file: Option<String>,
+    export_format: ExportFormat,
+    waypoints: Vec<Waypoint>,
+    publish_uds: Option<String>,
+    confirmation_versions: u64,
+    verify_balances: bool,
+    rpc_pool_max_idle: Option<usize>,
+    rpc_http2: bool,
+}
+
+impl DiemBridgeBuilder {
+    pub fn new(rpc_endpoint: &str) -> Self {
+        DiemBridgeBuilder {
+            rpc_endpoint: rpc_endpoint.to_string(),
+            chain_id_setting: ChainIdSetting::FromUrl,
+            state_file: None,
+            verify_only_new: false,
+            include_system_txns: false,
+            max_ledger_info_age_secs: None,
+            clock: Arc::new(SystemClock),
+            max_proof_bytes: DEFAULT_MAX_PROOF_BYTES,
+            fail_fast: false,
+            reset_cursor: false,
+            confirm_reset: false,
+            dry_run: false,
+            quiet: false,
+            dedup_window: DEFAULT_DEDUP_WINDOW,
+            fetch_window: DEFAULT_FETCH_WINDOW,
+            tx_cache_size: DEFAULT_TX_CACHE_SIZE,
+            log_verify_timing: false,
+            sync_timeout_secs: None,
+            strict: false,
+            include_events: true,
+            retry_policy: Arc::new(DefaultRetryPolicy),
+            rpc_retries: DEFAULT_RPC_RETRIES,
+            lag_ema_alpha: DEFAULT_LAG_EMA_ALPHA,
+            archive_endpoint: None,
+            retry_budget: DEFAULT_RETRY_BUDGET,
+            retry_budget_refill_per_sec: DEFAULT_RETRY_BUDGET_REFILL_PER_SEC,
+            batch_proofs: false,
+            call_timeout_secs: DEFAULT_CALL_TIMEOUT_SECS,
+            skip_failed_txns: false,
+            record_rpc: None,
+            replay_rpc: None,
+            export_file: None,
+            export_format: ExportFormat::Jsonl,
+            waypoints: vec![],
+            publish_uds: None,
+            confirmation_versions: 0,
+            verify_balances: false,
+            rpc_pool_max_idle: None,
+            rpc_http2: false,
+        }
+    }
+
+    pub fn chain_id(mut self, chain_id_setting: ChainIdSetting) -> Self {
+        self.chain_id_setting = chain_id_setting;
+        self
+    }
+
+    pub fn state_file(mut self, state_file: Option<String>) -> Self {
+        self.state_file = state_file;
+        self
+    }
+
+    pub fn verify_only_new(mut self, verify_only_new: bool) -> Self {
+        self.verify_only_new = verify_only_new;
+        self
+    }
+
+    pub fn include_system_txns(mut self, include_system_txns: bool) -> Self {
+        self.include_system_txns = include_system_txns;
+        self
+    }
+
+    /// Rejects ledger infos whose timestamp is older than `max_ledger_info_age_secs`
+    pub fn max_ledger_info_age_secs(mut self, max_ledger_info_age_secs: Option<u64>) -> Self {
+        self.max_ledger_info_age_secs = max_ledger_info_age_secs;
+        self
+    }
+
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Caps the size of each proof field accepted from the rpc endpoint
+    pub fn max_proof_bytes(mut self, max_proof_bytes: usize) -> Self {
+        self.max_proof_bytes = max_proof_bytes;
+        self
+    }
+
+    /// Aborts a sync pass on the first account failure instead of moving on to the next account
+    pub fn fail_fast(mut self, fail_fast: bool) -> Self {
+        self.fail_fast = fail_fast;
+        self
+    }
+
+    /// Discards the cursors in the state file so the whole history is verified and submitted again
+    ///
+    /// Transactions below the discarded cursors were already applied by the contract. Replaying
+    /// them is only safe if the contract ignores `VerifyTransaction` for versions it has seen, so a
+    /// reset must be explicitly confirmed with `confirm_reset`, or previewed with `dry_run`.
+    /// Every replayed version is logged.
+    pub fn reset_cursor(mut self, reset_cursor: bool, confirm_reset: bool) -> Self {
+        self.reset_cursor = reset_cursor;
+        self.confirm_reset = confirm_reset;
+        self
+    }
+
+    /// Logs the commands that would be pushed to the contract without submitting anything
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Leaves the per transaction details out of the log, warnings and errors are still logged
+    pub fn quiet(mut self, quiet: bool) -> Self {
+        self.quiet = quiet;
+        self
+    }
+
+    /// Number of recent versions per account remembered for dedup, see `DedupWindow`
+    pub fn dedup_window(mut self, dedup_window: usize) -> Self {
+        self.dedup_window = dedup_window;
+        self
+    }
+
+    /// Number of transactions fetched per rpc when looking transactions up by version
+    pub fn fetch_window(mut self, fetch_window: u64) -> Self {
+        self.fetch_window = fetch_window.max(1);
+        self
+    }
+
+    /// Number of fetched transactions kept around to answer repeated lookups without an rpc
+    pub fn tx_cache_size(mut self, tx_cache_size: usize) -> Self {
+        self.tx_cache_size = tx_cache_size;
+        self
+    }
+
+    /// Times each transaction proof verification, logging it and recording it in the summary
+    pub fn log_verify_timing(mut self, log_verify_timing: bool) -> Self {
+        self.log_verify_timing = log_verify_timing;
+        self
+    }
+
+    /// Gives up on an account once syncing it took longer than this, moving on to the next one
+    ///
+    /// The limit is a deadline shared by all the rpc and pRuntime calls of the account's pass.
+    pub fn sync_timeout_secs(mut self, sync_timeout_secs: Option<u64>) -> Self {
+        self.sync_timeout_secs = sync_timeout_secs;
+        self
+    }
+
+    /// Refuses to submit transactions verified with any anomaly, not only the fatal ones
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Whether transaction fetches ask the rpc to return the events inline
+    ///
+    /// Transaction proofs don't depend on the events, they are only carried along for logging.
+    pub fn include_events(mut self, include_events: bool) -> Self {
+        self.include_events = include_events;
+        self
+    }
+
+    /// Classifies failed rpc requests into retryable and permanent, see `DefaultRetryPolicy`
+    pub fn retry_policy(mut self, retry_policy: Arc<dyn RetryPolicy>) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// How many times a retryable rpc failure is retried before giving up
+    pub fn rpc_retries(mut self, rpc_retries: u32) -> Self {
+        self.rpc_retries = rpc_retries;
+        self
+    }
+
+    /// Weight of the latest pass in the sync lag moving average reported in the summary
+    pub fn lag_ema_alpha(mut self, lag_ema_alpha: f64) -> Self {
+        self.lag_ema_alpha = lag_ema_alpha;
+        self
+    }
+
+    /// Full history node asked for account states the rpc endpoint has pruned
+    pub fn archive_endpoint(mut self, archive_endpoint: Option<String>) -> Self {
+        self.archive_endpoint = archive_endpoint;
+        self
+    }
+
+    /// Bounds the rpc retries of the whole bridge on top of the per request `rpc_retries`, see
+    /// `RetryBudget`
+    pub fn retry_budget(mut self, retry_budget: u32, refill_per_sec: f64) -> Self {
+        self.retry_budget = retry_budget;
+        self.retry_budget_refill_per_sec = refill_per_sec;
+        self
+    }
+
+    /// Submits the sent transactions of each page in one `VerifyTransactions` command
+    ///
+    /// Requires a contract accepting `TransactionsWithProof`.
+    pub fn batch_proofs(mut self, batch_proofs: bool) -> Self {
+        self.batch_proofs = batch_proofs;
+        self
+    }
+
+    /// Timeout of a single rpc or pRuntime call, shortened to what's left of the account's
+    /// `sync_timeout_secs`
+    pub fn call_timeout_secs(mut self, call_timeout_secs: u64) -> Self {
+        self.call_timeout_secs = call_timeout_secs;
+        self
+    }
+
+    /// Skips transactions whose vm status isn't `executed` instead of submitting them
+    pub fn skip_failed_txns(mut self, skip_failed_txns: bool) -> Self {
+        self.skip_failed_txns = skip_failed_txns;
+        self
+    }
+
+    /// Appends every rpc exchange to this file, for `replay_rpc` to serve later
+    pub fn record_rpc(mut self, record_rpc: Option<String>) -> Self {
+        self.record_rpc = record_rpc;
+        self
+    }
+
+    /// Answers rpc requests from a file written with `record_rpc` instead of the endpoint
+    pub fn replay_rpc(mut self, replay_rpc: Option<String>) -> Self {
+        self.replay_rpc = replay_rpc;
+        self
+    }
+
+    /// Appends every verified transaction to `export_file`, in `export_format`
+    pub fn export(mut self, export_file: Option<String>, export_format: ExportFormat) -> Self {
+        self.export_file = export_file;
+        self.export_format = export_format;
+        self
+    }
+
+    /// Refuses epoch changes that match none of `waypoints`, no check if empty
+    pub fn waypoints(mut self, waypoints: Vec<Waypoint>) -> Self {
+        self.waypoints = waypoints;
+        self
+    }
+
+    /// Broadcasts every verified transaction to the readers of a Unix socket created at this path
+    pub fn publish_uds(mut self, publish_uds: Option<String>) -> Self {
+        self.publish_uds = publish_uds;
+        self
+    }
+
+    /// Defers transactions less than `confirmation_versions` behind the latest verified version
+    /// to a later pass
+    ///
+    /// Transactions near the tip are the ones a short reorg could still affect, so a nonzero
+    /// depth matters when the contract acts irreversibly on them, at the cost of that much lag.
+    pub fn confirmation_versions(mut self, confirmation_versions: u64) -> Self {
+        self.confirmation_versions = confirmation_versions;
+        self
+    }
+
+    /// Checks after each account pass that its balances match the ones of the previous pass plus
+    /// the transactions synced in between, reporting the divergence per currency
+    ///
+    /// A divergence is a warning, or fails the pass under `strict`.
+    pub fn verify_balances(mut self, verify_balances: bool) -> Self {
+        self.verify_balances = verify_balances;
+        self
+    }
+
+    /// Caps the idle connections pooled per rpc host, unlimited by default
+    pub fn rpc_pool_max_idle(mut self, rpc_pool_max_idle: Option<usize>) -> Self {
+        self.rpc_pool_max_idle = rpc_pool_max_idle;
+        self
+    }
+
+    /// Uses HTTP/2 with prior knowledge for the rpc clients
+    ///
+    /// An endpoint that only speaks HTTP/1.1 fails every request.
+    pub fn rpc_http2(mut self, rpc_http2: bool) -> Self {
+        self.rpc_http2 = rpc_http2;
+        self
+    }
+
+    pub fn build(self) -> Result<DiemBridge, Error> {
+        let url = Url::parse(&self.rpc_endpoint)
+            .map_err(|_| Error::InvalidConfig(format!("bad diem rpc endpoint: {}", self.rpc_endpoint)))?;
+        if self.verify_only_new && self.state_file.is_none() {
+            return Err(Error::InvalidConfig("--verify-only-new requires --state-file".to_string()));
+        }
+
+        let connection_options = ConnectionOptions {
+            http2_prior_knowledge: self.rpc_http2,
+            pool_max_idle_per_host: self.rpc_pool_max_idle,
+            ..ConnectionOptions::default()
+        };
+        let rpc = RpcEndpoint {
+            url: self.rpc_endpoint.clone(),
+            client: JsonRpcClient::with_options(url, connection_options.clone())
+                .map_err(|e| Error::InvalidConfig(format!("failed to create rpc client: {:?}", e)))?,
+        };
+        let archive = match &self.archive_endpoint {
+            Some(endpoint) => {
+                let url = Url::parse(endpoint)
+                    .map_err(|_| Error::InvalidConfig(format!("bad diem archive endpoint: {}", endpoint)))?;
+                Some(RpcEndpoint {
+                    url: endpoint.clone(),
+                    client: JsonRpcClient::with_options(url, connection_options)
+                        .map_err(|e| Error::InvalidConfig(format!("failed to create archive rpc client: {:?}", e)))?,
+                })
+            }
+            None => None,
+        };
+        let chain_id = match self.chain_id_setting {
+            ChainIdSetting::Fixed(chain_id) => chain_id,
+            _ if self.rpc_endpoint == "https://testnet.diem.com" => ChainId::new(NamedChain::TESTNET.id()),
+            _ => ChainId::new(NamedChain::TESTING.id()),
+        };
+        if self.reset_cursor && self.state_file.is_none() {
+            return Err(Error::InvalidConfig("--reset-cursor requires --state-file".to_string()));
+        }
+        if self.reset_cursor && !self.confirm_reset && !self.dry_run {
+            return Err(Error::InvalidConfig(
+                "--reset-cursor re-submits already applied transactions, pass --confirm-reset or --dry-run".to_string()
+            ));
+        }
+        let (cursors, reset_cursors) = match &self.state_file {
+            Some(state_file) if self.reset_cursor => {
+                let previous = SyncCursors::load(state_file)?;
+                println!("resetting sync cursors: {:?}", previous);
+                (SyncCursors::default(), Some(previous))
+            }
+            // Resumed from whether or not the transactions below them are trusted
+            Some(state_file) => {
+                let cursors = SyncCursors::load(state_file)?;
+                println!("loaded sync cursors: {:?}", cursors);
+                (cursors, None)
+            }
+            _ => (SyncCursors::default(), None),
+        };
+        let cassette = match (&self.record_rpc, &self.replay_rpc) {
+            (Some(_), Some(_)) => {
+                return Err(Error::InvalidConfig("--record-rpc and --replay-rpc are exclusive".to_string()));
+            }
+            (Some(path), None) => Some(Cassette::record(path)),
+            (None, Some(path)) => Some(Cassette::replay(path)?),
+            (None, None) => None,
+        };
+        let exporter = match &self.export_file {
+            Some(path) => Some(Exporter::open(path, self.export_format, self.dedup_window)?),
+            None => None,
+        };
+        let publisher = match &self.publish_uds {
+            Some(path) => Some(Publisher::bind(path)?),
+            None => None,
+        };
+        if !(self.lag_ema_alpha > 0.0 && self.lag_ema_alpha <= 1.0) {
+            return Err(Error::InvalidConfig(format!("lag ema alpha must be in (0, 1], got {}", self.lag_ema_alpha)));
+        }
+        println!("{}", self.rpc_endpoint);
+
+        // Seed the dedup windows with the versions submitted before a restart
+        let mut submitted_versions = BTreeMap::<String, DedupWindow>::new();
+        for (account_address, versions) in &cursors.submitted {
+            let window = submitted_versions.entry(account_address.clone())
+                .or_insert_with(|| DedupWindow::new(self.dedup_window));
+            for version in versions {
+                window.insert(*version);
+            }
+        }
+
+        Ok(DiemBridge {
+            chain_id,
+            chain_id_setting: self.chain_id_setting,
+            rpc,
+            archive,
+            cassette,
+            exporter,
+            publisher,
+            confirmation_versions: self.confirmation_versions,
+            verify_balances: self.verify_balances,
+            balance_ledgers: BTreeMap::new(),
+            stats: BridgeStats::default(),
+            rpc_errors: AtomicU64::new(0),
+            chain_version: AtomicU64::new(0),
+            waypoints: self.waypoints,
+            epoch_change_proof: None,
+            trusted_state: None,
+            latest_epoch_change_li: None,
+            latest_li: None,
+            received_versions: submitted_versions.clone(),
+            sent_versions: submitted_versions,
+            dedup_window: self.dedup_window,
+            // Keep room for a whole fetch window, or a range fetch would evict its own first entry
+            tx_cache: TransactionCache::new(self.tx_cache_size.max(self.fetch_window as usize)),
+            fetch_window: self.fetch_window,
+            account: BTreeMap::<String, AccountData>::new(),
+            cursors,
+            state_file: self.state_file,
+            verify_only_new: self.verify_only_new,
+            include_system_txns: self.include_system_txns,
+            transaction_counters: TransactionCounters::default(),
+            summary: SyncSummary::default(),
+            max_ledger_info_age_secs: self.max_ledger_info_age_secs,
+            clock: self.clock,
+            max_proof_bytes: self.max_proof_bytes,
+            fail_fast: self.fail_fast,
+            account_failures: BTreeMap::<String, u64>::new(),
+            reset_cursors,
+            dry_run: self.dry_run,
+            quiet: self.quiet,
+            log_verify_timing: self.log_verify_timing,
+            sync_timeout_secs: self.sync_timeout_secs,
+            strict: self.strict,
+            include_events: self.include_events,
+            retry_policy: self.retry_policy,
+            rpc_retries: self.rpc_retries,
+            retry_budget: Mutex::new(RetryBudget::new(self.retry_budget, self.retry_budget_refill_per_sec)),
+            batch_proofs: self.batch_proofs,
+            skip_failed_txns: self.skip_failed_txns,
+            call_timeout: Duration::from_secs(self.call_timeout_secs),
+            deadline: Deadline::none(),
+            lag_ema: Ema::new(self.lag_ema_alpha),
+            pending: BTreeMap::new(),
+            handled: BTreeMap::new(),
+            processed_version: None,
+            anchored_epoch: None,
+            rotated_keys: BTreeSet::new(),
+            address: Vec::new(),
+        })
+    }
+}
+
+impl DiemBridge {
+    pub fn new(url: &str) -> Result<Self> {
+        DiemBridgeBuilder::new(url)
+            .build()
+            .map_err(|e| anyhow::format_err!("failed to build DiemBridge: {:?}", e))
+    }
+
+    /// Persists the sync cursors to the state file, if one is configured
+    pub fn save_cursors(&self) -> Result<(), Error> {
+        match &self.state_file {
+            Some(state_file) if !self.dry_run => self.cursors.save(state_file),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn cursors(&self) -> &SyncCursors {
+        &self.cursors
+    }
+
+    /// Counters of the bridge since it was built, independent of how they're exposed
+    pub fn stats_snapshot(&self) -> BridgeStats {
+        BridgeStats {
+            rpc_errors: self.rpc_errors.load(Ordering::Relaxed),
+            chain_version: self.chain_version.load(Ordering::Relaxed),
+            ..self.stats.clone()
+        }
+    }
+
+    /// Versions of `address` discovered this pass and still waiting to be submitted to pRuntime
+    pub fn pending_versions(&self, address: &str) -> Vec<u64> {
+        self.pending.get(address).map_or_else(Vec::new, |versions| versions.iter().cloned().collect())
+    }
+
+    fn mark_pending(&mut self, account_address: &str, version: u64) {
+        self.pending.entry(account_address.to_string()).or_default().insert(version);
+    }
+
+    fn clear_pending(&mut self, account_address: &str, version: u64) {
+        if let Some(versions) = self.pending.get_mut(account_address) {
+            versions.remove(&version);
+        }
+        self.handled.entry(account_address.to_string()).or_default().insert(version);
+        self.processed_version = self.processed_version.max(Some(version));
+    }
+
+    /// Whether the other sync path already submitted or skipped `version` this pass
+    fn is_handled(&self, account_address: &str, version: u64) -> bool {
+        self.handled.get(account_address).map_or(false, |versions| versions.contains(&version))
+    }
+
+    /// Persists a submission marker right away instead of waiting for the end of the pass
+    fn record_submitted(&mut self, account_address: &str, version: u64) {
+        self.clear_pending(account_address, version);
+        // Bounded like the in-memory window, a zero limit would drop the marker right away
+        self.cursors.record_submitted(account_address, version, self.dedup_window.max(MIN_DEDUP_WINDOW));
+        if let Err(e) = self.save_cursors() {
+            println!("failed to save submission marker: {:?}", e);
+        }
+    }
+
+    /// Serializes the verified chain context and sync cursors for a standby bridge to import
+    pub fn export_snapshot(&self) -> Result<Vec<u8>, Error> {
+        let snapshot = BridgeSnapshot {
+            latest_epoch_change_li: self.latest_epoch_change_li.clone().ok_or(Error::StateNotInitialized)?,
+            latest_li: self.latest_li.clone().ok_or(Error::StateNotInitialized)?,
+            epoch_change_proof: self.epoch_change_proof.clone().ok_or(Error::StateNotInitialized)?,
+            cursors: self.cursors.clone(),
+        };
+        snapshot.encode()
+    }
+
+    /// Takes over the state exported by `export_snapshot`, re-verifying its ledger infos
+    ///
+    /// The snapshot's epoch changes must chain from one of the configured waypoints up to its
+    /// latest epoch change, so a snapshot is refused when no waypoint is set.
+    pub fn import_snapshot(&mut self, data: &[u8]) -> Result<(), Error> {
+        let snapshot = BridgeSnapshot::decode(data)?;
+        self.restore_chain_state(&snapshot)?;
+        self.cursors = snapshot.cursors;
+        // The snapshot may come from a bridge following another endpoint or chain
+        self.tx_cache.clear();
+        Ok(())
+    }
+
+    /// Adopts the verified chain context of `snapshot`, see `import_snapshot`
+    fn restore_chain_state(&mut self, snapshot: &BridgeSnapshot) -> Result<(), Error> {
+        if self.waypoints.is_empty() {
+            return Err(Error::InvalidSnapshot("importing a snapshot requires a waypoint".to_string()));
+        }
+        let trusted_state = self.waypoints.iter()
+            .find_map(|waypoint| {
+                match TrustedState::from(*waypoint)
+                    .verify_and_ratchet(&snapshot.latest_epoch_change_li, &snapshot.epoch_change_proof) {
+                    Ok(TrustedStateChange::Epoch { new_state, .. }) => Some(new_state),
+                    _ => None,
+                }
+            })
+            .ok_or_else(|| Error::InvalidSnapshot("epoch changes match none of the waypoints".to_string()))?;
+
+        self.trusted_state = Some(trusted_state);
+        self.latest_epoch_change_li = Some(snapshot.latest_epoch_change_li.clone());
+        self.verify_state_proof(snapshot.latest_li.clone(), EpochChangeProof::new(vec![], false))?;
+        self.latest_li = Some(snapshot.latest_li.clone());
+        self.epoch_change_proof = Some(snapshot.epoch_change_proof.clone());
+        Ok(())
+    }
+
+    /// Where the verified chain context is persisted next to the state file
+    fn trusted_state_path(&self) -> Option<String> {
+        self.state_file.as_ref().map(|state_file| format!("{}.trusted", state_file))
+    }
+
+    /// Persists the verified chain context next to the state file, so a restart ratchets from it
+    /// instead of verifying every epoch since genesis again
+    pub fn save_trusted_state(&self) -> Result<(), Error> {
+        match self.trusted_state_path() {
+            Some(path) if !self.dry_run && self.trusted_state.is_some() => write_atomic(&path, &self.export_snapshot()?),
+            _ => Ok(()),
+        }
+    }
+
+    /// Restores the chain context saved by `save_trusted_state`, checked against the waypoints
+    ///
+    /// The cursors come from the state file, not the snapshot. Without a waypoint, or with a file
+    /// that fails the check, the bridge verifies from genesis as if there were none.
+    pub fn load_trusted_state(&mut self) {
+        let path = match self.trusted_state_path() {
+            Some(path) if std::path::Path::new(&path).exists() => path,
+            _ => return,
+        };
+        if self.waypoints.is_empty() {
+            println!("No --waypoint to check {} against, verifying from genesis", path);
+            return;
+        }
+        let restored = std::fs::read(&path).map_err(Error::from)
+            .and_then(|data| BridgeSnapshot::decode(&data))
+            .and_then(|snapshot| self.restore_chain_state(&snapshot));
+        match restored {
+            Ok(()) => println!("Restored trusted state at version {} from {}",
+                self.trusted_state.as_ref().map_or(0, |s| s.latest_version()), path),
+            Err(e) => {
+                println!("Failed to restore trusted state from {}: {:?}, verifying from genesis", path, e);
+                self.trusted_state = None;
+                self.latest_epoch_change_li = None;
+                self.latest_li = None;
+                self.epoch_change_proof = None;
+            }
+        }
+    }
+
+    /// Whether `transaction` was already submitted before the cursors were reset
+    fn is_replay(&self, account_address: &str, transaction: &TransactionView) -> bool {
+        let previous = match &self.reset_cursors {
+            Some(previous) => previous,
+            None => return false,
+        };
+        match &transaction.transaction {
+            TransactionDataView::UserTransaction { sender, sequence_number, .. }
+                if sender.0.eq_ignore_ascii_case(account_address) => {
+                previous.sent_sequence.get(account_address).map_or(false, |s| sequence_number < s)
+            }
+            _ => previous.received_version.get(account_address).map_or(false, |v| transaction.version <= *v),
+        }
+    }
+
+    fn verify_state_proof(
+        &mut self,
+        li: LedgerInfoWithSignatures,
+        epoch_change_proof: EpochChangeProof
+    ) -> Result<(), Error> {
+        let client_version = self.trusted_state.as_ref().ok_or(Error::StateNotInitialized)?.latest_version();
+        // check ledger info version
+        if li.ledger_info().version() < client_version {
+            println!(
+                "Got stale ledger_info with version {}, known version: {}",
+                li.ledger_info().version(),
+                client_version,
+            );
+            return Err(Error::StaleStateProof { version: li.ledger_info().version(), known_version: client_version });
+        }
+
+        // check the node itself isn't stuck behind the network
+        if let Some(max_age) = self.max_ledger_info_age_secs {
+            let age = self.clock.now_secs().saturating_sub(li.ledger_info().timestamp_usecs() / 1_000_000);
+            if age > max_age {
+                println!("Got ledger_info {}s old, max age: {}s", age, max_age);
+                return Err(Error::StaleLedgerInfo { age });
+            }
+        }
+
+        // trusted_state_change
+        let change = self.trusted_state.as_ref().unwrap()
+            .verify_and_ratchet(&li, &epoch_change_proof)
+            .map_err(|e| classify_ratchet_error(e, li.ledger_info().version(), client_version))?;
+        match change {
+            TrustedStateChange::Epoch {
+                new_state,
+                latest_epoch_change_li,
+            } => {
+                if !self.waypoints.is_empty() {
+                    match matching_waypoint(&self.waypoints, &epoch_change_proof, client_version) {
+                        Some(waypoint) => println!("Epoch change matches waypoint {}", waypoint),
+                        None => {
+                            let epoch = latest_epoch_change_li.ledger_info().next_block_epoch();
+                            println!("Epoch change to {} matches none of the waypoints", epoch);
+                            return Err(Error::NoMatchingWaypoint { epoch });
+                        }
+                    }
+                }
+                println!(
+                    "Verified epoch changed to {}",
+                    latest_epoch_change_li
+                        .ledger_info()
+                        .next_epoch_state()
+                        .expect("no validator set in epoch change ledger info"),
+                );
+                // Update client state
+                self.summary.epoch_changes += 1;
+                self.trusted_state = Some(new_state);
+                self.latest_epoch_change_li = Some(latest_epoch_change_li.clone());
+            }
+            TrustedStateChange::Version { new_state } => {
+                if self.trusted_state.as_mut().unwrap().latest_version() < new_state.latest_version() {
+                    println!("Verified version change to: {}", new_state.latest_version());
+                }
+                self.trusted_state = Some(new_state);
+            }
+            TrustedStateChange::NoChange => (),
+        }
+        Ok(())
+    }
+
+    /// Cheap "where is the chain now" query: current version, timestamp and chain id,
+    /// without fetching or verifying any proof
+    pub fn get_chain_metadata(&mut self) -> Result<BlockMetadata, Error> {
+        let mut batch = JsonRpcBatch::new();
+        batch.add_get_metadata_request(None);
+        let resp = self.request_rpc(batch)?;
+        BlockMetadata::from_response(resp).map_err(|_| Error::FailedToDecode)
+    }
+
+    /// Checks the chain id and freshness of the endpoint before asking it for a state proof
+    fn check_chain_metadata(&mut self) -> Result<(), Error> {
+        if self.chain_id_setting == ChainIdSetting::FromUrl && self.max_ledger_info_age_secs.is_none() {
+            return Ok(());
+        }
+
+        let metadata = self.get_chain_metadata()?;
+        if self.chain_id_setting == ChainIdSetting::Auto {
+            if metadata.chain_id == 0 {
+                return Err(Error::FailedToDecode);
+            }
+            // Freeze the detected id so that any later change is reported as a mismatch
+            self.chain_id = ChainId::new(metadata.chain_id);
+            self.chain_id_setting = ChainIdSetting::Fixed(self.chain_id);
+            println!("Detected chain id: {}", self.chain_id);
+        } else if self.chain_id_setting != ChainIdSetting::FromUrl && metadata.chain_id != self.chain_id.id() {
+            println!("Chain id mismatch, expected: {}, got: {}", self.chain_id.id(), metadata.chain_id);
+            return Err(Error::ChainIdMismatch { expected: self.chain_id.id(), actual: metadata.chain_id });
+        }
+
+        if let Some(max_age) = self.max_ledger_info_age_secs {
+            // metadata timestamps are in microseconds
+            let age = self.clock.now_secs().saturating_sub(metadata.timestamp / 1_000_000);
+            if age > max_age {
+                println!("Endpoint is {}s behind at version {}, max age: {}s", age, metadata.version, max_age);
+                return Err(Error::StaleLedgerInfo { age });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Fetches the state proof from the rpc endpoint and ratchets the trusted state to it
+    ///
+    /// Only the first call fetches the epoch changes from version 0, later ones (and those after
+    /// `import_snapshot`) go through `ratchet_to_latest`.
+    ///
+    /// Returns the zeroth epoch change ledger info, the latest ledger info and the epoch change
+    /// proof linking them.
+    fn refresh_trusted_state(
+        &mut self,
+    ) -> Result<(LedgerInfoWithSignatures, LedgerInfoWithSignatures, EpochChangeProof), Error> {
+        if self.trusted_state.is_some() && self.epoch_change_proof.is_some() {
+            self.ratchet_to_latest()?;
+            let epoch_change_proof = self.epoch_change_proof.clone().ok_or(Error::StateNotInitialized)?;
+            let zero_ledger_info_with_sigs = epoch_change_proof.ledger_info_with_sigs[0].clone();
+            let latest_li = self.latest_li.clone().ok_or(Error::StateNotInitialized)?;
+            return Ok((zero_ledger_info_with_sigs, latest_li, epoch_change_proof));
+        }
+        self.check_chain_metadata()?;
+
+        let mut batch = JsonRpcBatch::new();
+        batch.add_get_state_proof_request(0);
+        let resp = self.request_rpc(batch).map_err(|_| {
+            println!("Failed to get init_state");
+            Error::FailedToInitState
+        })?;
+        let state_proof = StateProofView::from_response(resp).map_err(|_| Error::FailedToDecode)?;
+
+        let epoch_change_proof: EpochChangeProof =
+            decode_bcs_hex(state_proof.epoch_change_proof, "epoch_change_proof", self.max_proof_bytes)?;
+        let ledger_info_with_signatures: LedgerInfoWithSignatures =
+            decode_bcs_hex(state_proof.ledger_info_with_signatures, "ledger_info_with_signatures", self.max_proof_bytes)?;
+
+        // Init zero version state
+        let zero_ledger_info_with_sigs = epoch_change_proof.ledger_info_with_sigs[0].clone();
+
+        self.latest_epoch_change_li = Some(zero_ledger_info_with_sigs.clone());
+        self.trusted_state = Some(TrustedState::try_from(zero_ledger_info_with_sigs.ledger_info()).unwrap());
+        self.latest_li = Some(ledger_info_with_signatures.clone());
+        self.epoch_change_proof = Some(epoch_change_proof.clone());
+
+        // Update Latest version state
+        let verified = match self.verify_state_proof(ledger_info_with_signatures.clone(), epoch_change_proof.clone()) {
+            Ok(()) | Err(Error::StaleStateProof { .. }) => {
+                check_ledger_state(&ledger_info_with_signatures, self.latest_epoch_change_li.as_ref())
+            }
+            e => e,
+        };
+        if let Err(e) = verified {
+            // Don't keep a proof we failed to verify around for the transaction proofs
+            println!("Failed to verify state proof: {:?}", e);
+            self.latest_li = None;
+            self.epoch_change_proof = None;
+            return Err(e);
+        }
+        println!("trusted_state: {:#?}", self.trusted_state);
+        println!("ledger_info_with_signatures: {:#?}", self.latest_li);
+
+        Ok((zero_ledger_info_with_sigs, ledger_info_with_signatures, epoch_change_proof))
+    }
+
+    /// Ratchets the trusted state to the latest ledger info, fetching only the epoch changes since
+    /// the trusted version instead of the whole history
+    ///
+    /// The new epoch changes are appended to the kept epoch change proof, which still starts at
+    /// the zeroth epoch for the transaction proofs.
+    pub fn ratchet_to_latest(&mut self) -> Result<(), Error> {
+        self.ratchet(false).map(|_| ())
+    }
+
+    /// Like `ratchet_to_latest`, but leaves the trusted state alone and returns `false` when the
+    /// latest ledger info is in a later epoch than the trusted one
+    ///
+    /// Used within a pass: the contract only learns about an epoch change from the commands
+    /// `init_state` pushes at the start of the next pass, so a proof against the new epoch would
+    /// be rejected until then.
+    fn ratchet_within_epoch(&mut self) -> Result<bool, Error> {
+        self.ratchet(true)
+    }
+
+    fn ratchet(&mut self, within_epoch: bool) -> Result<bool, Error> {
+        let trusted_version = self.trusted_state.as_ref().ok_or(Error::StateNotInitialized)?.latest_version();
+        let known_proof = self.epoch_change_proof.clone().ok_or(Error::StateNotInitialized)?;
+        self.check_chain_metadata()?;
+
+        let mut batch = JsonRpcBatch::new();
+        batch.add_get_state_proof_request(trusted_version);
+        let resp = self.request_rpc(batch).map_err(|_| {
+            println!("Failed to get state proof since version {}", trusted_version);
+            Error::FailedToGetResponse
+        })?;
+        let state_proof = StateProofView::from_response(resp).map_err(|_| Error::FailedToDecode)?;
+        let delta: EpochChangeProof =
+            decode_bcs_hex(state_proof.epoch_change_proof, "epoch_change_proof", self.max_proof_bytes)?;
+        let ledger_info_with_signatures: LedgerInfoWithSignatures =
+            decode_bcs_hex(state_proof.ledger_info_with_signatures, "ledger_info_with_signatures", self.max_proof_bytes)?;
+        println!("Fetched {} epoch changes since version {}", delta.ledger_info_with_sigs.len(), trusted_version);
+
+        // The epoch the trusted validator set signs, the contract was given the same one
+        let trusted_epoch = self.validator_set().map(|epoch_state| epoch_state.epoch);
+        if within_epoch && trusted_epoch.map_or(false, |epoch| ledger_info_with_signatures.ledger_info().epoch() > epoch) {
+            println!("epoch changed to {} during the pass, left to the next pass",
+                ledger_info_with_signatures.ledger_info().epoch());
+            return Ok(false);
+        }
+        match self.verify_state_proof(ledger_info_with_signatures.clone(), delta.clone()) {
+            Ok(()) => (),
+            Err(Error::StaleStateProof { .. }) => return Ok(true),
+            Err(e) => return Err(e),
+        }
+        check_ledger_state(&ledger_info_with_signatures, self.latest_epoch_change_li.as_ref())?;
+        self.latest_li = Some(ledger_info_with_signatures);
+        self.epoch_change_proof = Some(extend_epoch_change_proof(known_proof, delta));
+        Ok(true)
+    }
+
+    /// The validator set of the latest verified epoch
+    pub fn validator_set(&self) -> Option<&EpochState> {
+        self.latest_epoch_change_li.as_ref()?.ledger_info().next_epoch_state()
+    }
+
+    async fn init_state<P: PRuntime, S: CommandSink>(
+        &mut self,
+        pr: Option<&P>,
+        sink: &mut S,
+        initialized: bool,
+    ) -> Result<(), Error> {
+        let commands = self.init_commands(initialized)?;
+        if pr.is_some() {
+            for command in commands {
+                let command_value = serde_json::to_value(&command)?;
+                let _ = self.push_command(command_value.to_string(), sink).await;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Refreshes the trusted state and returns the commands `init_state` pushes to the contract
+    ///
+    /// `SetTrustedState` goes on every call, first when connecting and then on the epoch changes
+    /// of later loops, see `trusted_state_anchor`; `VerifyEpochProof` on the loops only.
+    fn init_commands(&mut self, initialized: bool) -> Result<Vec<CommandReqData>, Error> {
+        let (zero_ledger_info_with_sigs, ledger_info_with_signatures, epoch_change_proof) =
+            self.refresh_trusted_state()?;
+
+        let mut commands = Vec::new();
+        if let Some(anchor) = self.trusted_state_anchor(&zero_ledger_info_with_sigs) {
+            let trusted_state_b64 = encode_b64_bcs(&anchor)?;
+            commands.push(CommandReqData::SetTrustedState { trusted_state_b64, chain_id: self.chain_id.id() });
+        }
+        if !initialized {
+            let ledger_info_with_signatures_b64 = encode_b64_bcs(&ledger_info_with_signatures)?;
+            let epoch_change_proof_b64 = encode_b64_bcs(&epoch_change_proof)?;
+            commands.push(CommandReqData::VerifyEpochProof { ledger_info_with_signatures_b64, epoch_change_proof_b64 });
+        }
+        Ok(commands)
+    }
+
+    /// Picks the ledger info to submit with `SetTrustedState`, if any
+    ///
+    /// The zeroth ledger info is submitted the first time only; afterwards the contract is
+    /// re-anchored to the latest epoch change ledger info once the verified epoch moved past the
+    /// anchored one, and nothing is submitted otherwise.
+    fn trusted_state_anchor(&mut self, zero_li: &LedgerInfoWithSignatures) -> Option<LedgerInfoWithSignatures> {
+        match (self.anchored_epoch, self.latest_epoch_change_li.as_ref()) {
+            (None, _) => {
+                self.anchored_epoch = Some(zero_li.ledger_info().next_block_epoch());
+                Some(zero_li.clone())
+            }
+            (Some(anchored), Some(li)) if li.ledger_info().next_block_epoch() > anchored => {
+                self.anchored_epoch = Some(li.ledger_info().next_block_epoch());
+                Some(li.clone())
+            }
+            _ => None,
+        }
+    }
+
+    async fn sync_account<P: PRuntime, S: CommandSink>(
+        &mut self,
+        pr: &P,
+        account_address: String,
+        sink: &mut S,
+    ) -> Result<(), Error> {
+        // Init account information; addresses also come from the contract, which isn't validated
+        let address = AccountAddress::from_hex_literal(&("0x".to_string() + &account_address))
+            .map_err(|_| Error::InvalidConfig(format!("bad account address: {}", account_address)))?;
+        let mut account_view = match self.get_account_view(address)? {
+            Some(account_view) => account_view,
+            None => {
+                println!("account {} doesn't exist on chain, nothing to sync", account_address);
+                self.summary.accounts.entry(account_address).or_default().account_missing = true;
+                return Ok(());
+            }
+        };
+
+        let mut refetches = 0;
+        let mut currencies: Vec<String>;
+        let mut received_caught_up;
+        loop {
+            currencies = account_view.balances.iter().map(|balance| balance.currency.clone()).collect();
+            received_caught_up = self.sync_account_view(pr, account_address.clone(), address, account_view, sink).await?;
+            // The account may have sent transactions since its view was fetched, which the pinned
+            // sequence number would leave out along with a stale account info
+            match self.account_advanced(&account_address)? {
+                Refetch::Advanced(latest) if refetches < MAX_ACCOUNT_REFETCHES => {
+                    println!("account {} advanced to sequence {} during the pass, refetching",
+                        account_address, latest.sequence_number);
+                    refetches += 1;
+                    account_view = latest;
+                }
+                Refetch::Advanced(latest) => {
+                    println!("account {} still advancing (sequence {}), left to the next pass",
+                        account_address, latest.sequence_number);
+                    return Ok(());
+                }
+                // The balances are behind the transactions left out as well
+                Refetch::EpochChanged => return Ok(()),
+                Refetch::Current => break,
+            }
+        }
+        // Received events left to later passes would show up as a divergence
+        if self.verify_balances && received_caught_up {
+            self.check_balances(&account_address, address, &currencies)?;
+        } else if self.verify_balances {
+            println!("balances of {} not checked, received events are still being synced", account_address);
+        }
+
+        Ok(())
+    }
+
+    /// Records the balance change of `transaction` for `--verify-balances`
+    fn record_balance_delta(&mut self, account_address: &str, transaction: &TransactionView) {
+        if !self.verify_balances {
+            return;
+        }
+        let delta = balance_delta(&account_address.to_ascii_lowercase(), transaction);
+        self.balance_ledgers.entry(account_address.to_string()).or_default().record(transaction.version, delta);
+    }
+
+    /// Compares the balances of `address` with the ones expected from the previous check and the
+    /// transactions synced since
+    ///
+    /// The balances are read from the account state proven at the highest confirmed version,
+    /// the last one the pass synced transactions up to, so a transaction landing while the pass
+    /// runs is left to the next check instead of showing up as a divergence.
+    fn check_balances(&mut self, account_address: &str, address: AccountAddress, currencies: &[String]) -> Result<(), Error> {
+        let trusted_version = self.trusted_state.as_ref().ok_or(Error::StateNotInitialized)?.latest_version();
+        let version = trusted_version.saturating_sub(self.confirmation_versions);
+        let account_state = match self.proven_account_state(address, version)? {
+            Some(blob) => AccountState::try_from(&blob).map_err(|_| Error::FailedToDecode)?,
+            None => return Ok(()),
+        };
+        let currency_codes: Vec<_> = currencies.iter()
+            .filter_map(|currency| from_currency_code_string(currency).ok())
+            .collect();
+        let balances = account_state.get_balance_resources(&currency_codes)
+            .map_err(|_| Error::FailedToDecode)?
+            .into_iter()
+            .map(|(currency, balance)| (currency.to_string(), balance.coin()))
+            .collect();
+        let discrepancies = self.balance_ledgers.entry(account_address.to_string()).or_default()
+            .check(version, balances);
+        for (currency, discrepancy) in &discrepancies {
+            println!("warning: balance of {} at version {} diverges from the synced transactions by {} {}",
+                account_address, version, discrepancy, currency);
+        }
+        if self.strict && !discrepancies.is_empty() {
+            return Err(Error::BalanceMismatch { account: account_address.to_string(), version });
+        }
+        Ok(())
+    }
+
+    /// The state blob of `address` at `version`, proven against the latest trusted ledger info
+    ///
+    /// `None` if the account didn't exist yet at that version.
+    fn proven_account_state(&mut self, address: AccountAddress, version: u64) -> Result<Option<AccountStateBlob>, Error> {
+        let latest_li = self.latest_li.clone().ok_or(Error::StateNotInitialized)?;
+        let mut batch = JsonRpcBatch::new();
+        batch.add_get_account_state_with_proof_request(address, Some(version), Some(latest_li.ledger_info().version()));
+        let resp = self.request_historical_rpc(batch)?;
+        let view = AccountStateWithProofView::from_response(resp).map_err(|_| Error::FailedToDecode)?;
+        let blob: AccountStateBlob = match view.blob {
+            Some(blob) => decode_bcs_hex(blob, "account_state_blob", self.max_proof_bytes)?,
+            None => return Ok(None),
+        };
+        let account_state_proof = AccountStateProof::new(
+            TransactionInfoWithProof::new(
+                decode_bcs_hex(
+                    view.proof.ledger_info_to_transaction_info_proof,
+                    "ledger_info_to_transaction_info_proof",
+                    self.max_proof_bytes,
+                )?,
+                decode_bcs_hex(view.proof.transaction_info, "transaction_info", self.max_proof_bytes)?,
+            ),
+            decode_bcs_hex(
+                view.proof.transaction_info_to_account_proof,
+                "transaction_info_to_account_proof",
+                self.max_proof_bytes,
+            )?,
+        );
+        account_state_proof
+            .verify(latest_li.ledger_info(), version, address.hash(), Some(&blob))
+            .map_err(|e| Error::InvalidStateProof(e.to_string()))?;
+        Ok(Some(blob))
+    }
+
+    /// The account view of `address`, `None` if the rpc answered that it doesn't exist
+    ///
+    /// Failing to get an answer at all is an `Err`, never mistaken for a missing account.
+    fn get_account_view(&mut self, address: AccountAddress) -> Result<Option<AccountView>, Error> {
+        let mut batch = JsonRpcBatch::new();
+        batch.add_get_account_request(address);
+        let resp = self.request_rpc(batch)?;
+        AccountView::optional_from_response(resp).map_err(|_| Error::FailedToDecode)
+    }
+
+    /// Refetches the account view, returning it if the sequence number moved past the one the
+    /// current pass pinned
+    ///
+    /// The trusted state is ratcheted to the latest ledger info first in that case: the new
+    /// transactions are past the version it was at, and couldn't be proven against it. The
+    /// ratchet stays within the epoch of the pass, every proof of the pass is checked under the
+    /// validator set the contract was given; past an epoch change the account is left to the
+    /// next pass instead.
+    fn account_advanced(&mut self, account_address: &str) -> Result<Refetch, Error> {
+        let (address, pinned) = match self.account.get(account_address) {
+            Some(account) => (account.address, account.sequence_number),
+            None => return Err(Error::StateNotInitialized),
+        };
+        match self.get_account_view(address)?.filter(|view| view.sequence_number > pinned) {
+            Some(latest) if self.ratchet_within_epoch()? => Ok(Refetch::Advanced(latest)),
+            Some(_) => Ok(Refetch::EpochChanged),
+            None => Ok(Refetch::Current),
+        }
+    }
+
+    /// Syncs the account with `account_view`, returning whether its received events caught up,
+    /// see `sync_receiving_transactions`
+    async fn sync_account_view<P: PRuntime, S: CommandSink>(
+        &mut self,
+        pr: &P,
+        account_address: String,
+        address: AccountAddress,
+        account_view: AccountView,
+        sink: &mut S,
+    ) -> Result<bool, Error> {
+        // Event keys come straight from the rpc endpoint, reject anomalies before using them
+        let sent_events_key = validate_event_key(&account_view.sent_events_key, "sent_events_key")?;
+        let received_events_key = validate_event_key(&account_view.received_events_key, "received_events_key")?;
+
+        let authentication_key = account_view.authentication_key.into_bytes().ok();
+        // A rotated key is legitimate, but a mismatch can also flag a spoofed rpc response
+        if let Err(e) = check_auth_key(address, authentication_key.as_deref()) {
+            if self.strict {
+                return Err(e);
+            }
+            // The key stays rotated, warning on every pass would only bury the rest of the log
+            if self.rotated_keys.insert(account_address.clone()) {
+                println!("warning: {:?}", e);
+            }
+        }
+        self.account.insert(account_address.clone(), AccountData {
+            address,
+            authentication_key,
+            key_pair: None,
+            sequence_number: account_view.sequence_number,
+            status: AccountStatus::Persisted,
+        });
+
+        let balances = Some(account_view.balances.clone());
+
+        let mut amounts = Vec::<Amount>::new();
+        for b in balances.as_ref().unwrap() {
+            let currency = match normalize_currency(self.chain_id, &b.currency) {
+                Ok(currency) => currency,
+                Err(e) if self.strict => return Err(e),
+                Err(e) => {
+                    println!("warning: {:?}, passed through", e);
+                    b.currency.clone()
+                }
+            };
+            amounts.push(Amount { amount: b.amount, currency });
+        }
+        let account = self.account.get(&account_address).unwrap();
+        let account_info = AccountInfo {
+            address: account.address,
+            authentication_key: account.authentication_key.clone(),
+            sequence_number: account.sequence_number,
+            sent_events_key,
+            received_events_key: received_events_key.clone(),
+            balances: amounts,
+        };
+
+        let account_info_b64 = encode_b64_bcs(&account_info)?;
+        let command_value = serde_json::to_value(&CommandReqData::AccountInfo { account_info_b64 })?;
+        let _ = self.push_command(command_value.to_string(), sink).await;
+
+        // Sync receiving transactions
+        let received_caught_up = self.sync_receiving_transactions(
+            pr,
+            received_events_key,
+            RECEIVING_EVENTS_LIMIT,
+            account_address.clone(),
+            sink,
+        ).await?;
+
+        // Sync sending transactions
+        let _ = self.sync_sent_transactions(account_address, sink).await?;
+
+        Ok(received_caught_up)
+    }
+
+    /// Asks the contract for the highest received event sequence number it has processed
+    async fn query_event_cursor<P: PRuntime>(&self, pr: &P, event_key: &str) -> Option<u64> {
+        let request = QueryReqData::GetEventCursor { event_key: event_key.to_string() };
+        match self.deadline.run(self.call_timeout, pr.query(DIEM_CONTRACT_ID, request)).await {
+            Ok(QueryRespData::GetEventCursor { sequence }) => sequence,
+            Ok(resp) => {
+                println!("unexpected event cursor response: {:?}", resp);
+                None
+            }
+            Err(e) => {
+                println!("query event cursor error: {:?}", e);
+                None
+            }
+        }
+    }
+
+    /// Syncs up to `limit` received events, returning whether every received event up to the
+    /// confirmed versions is synced
+    async fn sync_receiving_transactions<P: PRuntime, S: CommandSink>(
+        &mut self,
+        pr: &P,
+        received_events_key: String,
+        limit: u64,
+        account_address: String,
+        sink: &mut S,
+    ) -> Result<bool, Error> {
+        // Resume from whichever of the bridge and the contract got further
+        let local_sequence = self.cursors.received_sequence.get(&account_address).cloned().unwrap_or(0);
+        let contract_sequence = self.query_event_cursor(pr, &received_events_key).await.map_or(0, |s| s + 1);
+        let start = cmp::max(local_sequence, contract_sequence);
+        if start > local_sequence {
+            println!("contract is ahead of local event cursor, skip to {}", start);
+            self.cursors.received_sequence.insert(account_address.clone(), start);
+        }
+
+        let mut batch = JsonRpcBatch::new();
+        batch.add_get_events_request(received_events_key.to_string(), start, limit);
+        let resp = self.request_rpc(batch).map_err(|_| Error::FailedToGetReceivingTransactions)?;
+
+        let received_events = EventView::vec_from_response(resp).map_err(|_| Error::FailedToDecode)?;
+        // A full page may have more events after it
+        let mut caught_up = (received_events.len() as u64) < limit;
+        let received_version = self.cursors.received_version.get(&account_address).cloned();
+        let is_new = |diem: &DiemBridge, event: &EventView| {
+            let exist = diem.received_versions.get(&account_address)
+                .map_or(false, |w| w.contains(event.transaction_version));
+            // Versions at or below the persisted cursor were already verified before a restart
+            let verified = diem.verify_only_new
+                && received_version.map_or(false, |v| event.transaction_version <= v);
+            !exist && !verified && !diem.is_handled(&account_address, event.transaction_version)
+        };
+        for event in &received_events {
+            if is_new(self, event) && self.is_confirmed(event.transaction_version) {
+                self.mark_pending(&account_address, event.transaction_version);
+            }
+        }
+        for event in received_events {
+            if !self.is_confirmed(event.transaction_version) {
+                // Keep the cursor at this event, it's retried once deep enough
+                println!("received transaction {} isn't confirmed yet, deferred", event.transaction_version);
+                break;
+            }
+            if is_new(self, &event) {
+                if !self.quiet {
+                    println!("new received event!");
+                }
+                self.summary.accounts.entry(account_address.clone()).or_default().events_seen += 1;
+                self.stats.events_processed += 1;
+                let transaction = match self.get_transaction_by_version(event.transaction_version) {
+                    Ok(transaction) => transaction,
+                    Err(e) => {
+                        // The cursor stays at this event; failing the account reports it and
+                        // has the next pass retry it
+                        println!("get_transaction_by_version error: {:?}", e);
+                        return Err(e);
+                    }
+                };
+                if !self.quiet {
+                    println!("received transaction:{:?}", transaction);
+                }
+                self.record_balance_delta(&account_address, &transaction);
+                match &transaction.transaction {
+                    TransactionDataView::UserTransaction {..} => {
+                        match self.submit_or_skip(&transaction, account_address.clone(), sink).await {
+                            Ok(true) => self.record_submitted(&account_address, transaction.version),
+                            Ok(false) => (),
+                            Err(e) => {
+                                // Keep the cursor at this event so it is retried in the next loop
+                                println!("sync received transaction error: {:?}", e);
+                                caught_up = false;
+                                break;
+                            }
+                        }
+                    }
+                    // The contract only verifies user transactions; a write set or other system
+                    // transaction emitting a received event has nothing it could check
+                    other => {
+                        self.transaction_counters.record(other);
+                        let kind = match other {
+                            TransactionDataView::BlockMetadata {..} => "block metadata",
+                            TransactionDataView::WriteSet {..} => "write set",
+                            _ => "unknown",
+                        };
+                        self.record_skip(&account_address, transaction.version, format!("{} transaction", kind));
+                    }
+                }
+                let version = self.cursors.received_version.entry(account_address.clone()).or_insert(0);
+                *version = cmp::max(*version, transaction.version);
+            }
+
+            self.cursors.received_sequence.insert(account_address.clone(), event.sequence_number + 1);
+            let dedup_window = self.dedup_window;
+            self.received_versions.entry(account_address.clone())
+                .or_insert_with(|| DedupWindow::new(dedup_window))
+                .insert(event.transaction_version);
+        }
+
+        Ok(caught_up)
+    }
+
+    async fn sync_sent_transactions<S: CommandSink>(
+        &mut self,
+        account_address: String,
+        sink: &mut S,
+    ) -> Result<(), Error> {
+        if !self.quiet {
+            println!("account:{:?}", self.account);
+        }
+        let account = self.account.get(&account_address).ok_or(Error::StateNotInitialized)?;
+        let (address, sequence_number) = (account.address, account.sequence_number);
+        // Only request the sent transactions in [last_synced, current) sequence number range
+        let mut start = self.cursors.sent_sequence.get(&account_address).cloned().unwrap_or(0);
+        // The rpc caps the number of transactions returned per call, page through the range
+        while start < sequence_number {
+            let mut batch = JsonRpcBatch::new();
+            batch.add_get_account_transactions_request(
+                address,
+                start,
+                cmp::min(sequence_number - start, ACCOUNT_TRANSACTIONS_PAGE_SIZE),
+                self.include_events,
+            );
+            let resp = self.request_rpc(batch).map_err(|_| Error::FailedToGetSentTransactions)?;
+            if !self.quiet {
+                println!("add_get_account_transactions_request resp:{:?}", resp);
+            }
+            let mut transactions = TransactionView::vec_from_response(resp).map_err(|_| Error::FailedToDecode)?;
+            // Sent transactions come in version order, the unconfirmed ones are a tail left to a
+            // later pass
+            let confirmed = transactions.iter().take_while(|t| self.is_confirmed(t.version)).count();
+            let deferred = confirmed < transactions.len();
+            if deferred {
+                println!("{} sent transactions aren't confirmed yet, deferred", transactions.len() - confirmed);
+                transactions.truncate(confirmed);
+            }
+            if transactions.is_empty() {
+                break;
+            }
+            let page_len = transactions.len() as u64;
+            for transaction in &transactions {
+                self.record_balance_delta(&account_address, transaction);
+            }
+            if self.batch_proofs {
+                self.sync_sent_page_batched(&account_address, start, transactions, sink).await?;
+                start += page_len;
+                if deferred {
+                    break;
+                }
+                continue;
+            }
+            for transaction in &transactions {
+                self.tx_cache.insert(transaction.clone());
+                let exist = self.sent_versions.get(&account_address)
+                    .map_or(false, |w| w.contains(transaction.version))
+                    || self.is_handled(&account_address, transaction.version);
+                if !exist && matches!(transaction.transaction, TransactionDataView::UserTransaction {..}) {
+                    self.mark_pending(&account_address, transaction.version);
+                }
+            }
+            for (i, transaction) in transactions.into_iter().enumerate() {
+                let exist = self.sent_versions.get(&account_address)
+                    .map_or(false, |w| w.contains(transaction.version))
+                    || self.is_handled(&account_address, transaction.version);
+                if !exist {
+                    if !self.quiet {
+                        println!("new transaction!");
+                    }
+                    self.transaction_counters.record(&transaction.transaction);
+                    match transaction.transaction {
+                        TransactionDataView::UserTransaction {..} => {
+                            if self.submit_or_skip(&transaction, account_address.clone(), sink).await? {
+                                self.record_submitted(&account_address, transaction.version);
+                            }
+                        },
+                        // System transactions carry no account state proof for pRuntime, only record them
+                        _ if self.include_system_txns => {
+                            println!("system transaction {}: {:?}", transaction.version, transaction.transaction);
+                        },
+                        _ => (),
+                    }
+                }
+
+                // Advance the cursor only once the transaction's proof has been submitted
+                self.cursors.sent_sequence.insert(account_address.clone(), start + i as u64 + 1);
+                let dedup_window = self.dedup_window;
+                self.sent_versions.entry(account_address.clone())
+                    .or_insert_with(|| DedupWindow::new(dedup_window))
+                    .insert(transaction.version);
+            }
+            start += page_len;
+            if deferred {
+                break;
+            }
+        }
+
+        println!("transaction counters: {:?}", self.transaction_counters);
+
+        Ok(())
+    }
+
+    /// Like the per transaction path of `sync_sent_transactions`, but proves the whole page first
+    /// and submits the proofs in a single command
+    ///
+    /// On a transient failure the proofs collected so far are still submitted and the cursor
+    /// stops at the failed transaction. The cursor moves past each group as it's submitted, so a
+    /// failed submission only leaves that group and the following ones to the next pass.
+    async fn sync_sent_page_batched<S: CommandSink>(
+        &mut self,
+        account_address: &str,
+        start: u64,
+        transactions: Vec<TransactionView>,
+        sink: &mut S,
+    ) -> Result<(), Error> {
+        let mut proofs = Vec::new();
+        let mut synced = 0;
+        let mut failure = None;
+        for transaction in &transactions {
+            self.tx_cache.insert(transaction.clone());
+            let exist = self.sent_versions.get(account_address)
+                .map_or(false, |w| w.contains(transaction.version))
+                || self.is_handled(account_address, transaction.version);
+            if !exist {
+                self.transaction_counters.record(&transaction.transaction);
+                if let TransactionDataView::UserTransaction {..} = transaction.transaction {
+                    self.mark_pending(account_address, transaction.version);
+                    if let Some(reason) = self.failed_on_chain(transaction) {
+                        self.record_skip(account_address, transaction.version, reason);
+                        synced += 1;
+                        continue;
+                    }
+                    match self.get_transaction_proof(account_address.to_string(), transaction) {
+                        Ok(proof) => proofs.push(proof),
+                        Err(Error::VersionPruned) => self.record_pruned(account_address, transaction.version),
+                        Err(e) if !e.is_transient() => self.record_skip(
+                            account_address, transaction.version, format!("permanently rejected: {:?}", e)),
+                        Err(e) => {
+                            println!("get_transaction_proof error: {:?}", e);
+                            failure = Some(e);
+                            break;
+                        }
+                    }
+                }
+            }
+            synced += 1;
+        }
+
+        // Exported and published as their group is submitted, the grouping drops what they need
+        let verified: BTreeMap<u64, TransactionWithProof> = if self.exporter.is_some() || self.publisher.is_some() {
+            proofs.iter().map(|proof| (proof.version, proof.clone())).collect()
+        } else {
+            BTreeMap::new()
+        };
+        let groups = TransactionsWithProof::group(proofs);
+        // The cursor advances up to the first transaction of the next group to submit
+        let position = |version: u64| transactions.iter().position(|t| t.version == version).unwrap_or(synced);
+        let bounds: Vec<usize> = groups.iter().skip(1).map(|group| position(group.transactions[0].version))
+            .chain(std::iter::once(synced))
+            .collect();
+        let mut advanced = groups.first().map_or(synced, |group| position(group.transactions[0].version));
+        self.advance_sent_cursor(account_address, start, &transactions[..advanced]);
+        for (group, bound) in groups.into_iter().zip(bounds) {
+            let versions = group.versions();
+            let transactions_with_proof_b64 = encode_b64_bcs(&group)?;
+            let command_value = serde_json::to_value(&CommandReqData::VerifyTransactions {
+                account_address: account_address.to_string(),
+                transactions_with_proof_b64,
+            })?;
+            self.push_command(command_value.to_string(), sink).await?;
+            self.summary.accounts.entry(account_address.to_string()).or_default().transactions_processed += versions.len() as u64;
+            self.stats.transactions_submitted += versions.len() as u64;
+            for version in versions {
+                self.record_submitted(account_address, version);
+                if let (Some(proof), Some(transaction)) =
+                    (verified.get(&version), transactions.iter().find(|t| t.version == version)) {
+                    self.emit_verified(account_address, transaction, proof);
+                }
+            }
+            self.advance_sent_cursor(account_address, start + advanced as u64, &transactions[advanced..bound]);
+            advanced = bound;
+        }
+
+        failure.map_or(Ok(()), Err)
+    }
+
+    /// Appends a submitted transaction to the export file and publishes it, if configured
+    ///
+    /// The command already went through, so an export failure is logged rather than failing the
+    /// submission, which would only get it submitted again.
+    fn emit_verified(&mut self, account_address: &str, transaction: &TransactionView, proof: &TransactionWithProof) {
+        if let Some(exporter) = &mut self.exporter {
+            if let Err(e) = exporter.write(&ExportRecord { account_address, transaction, proof }) {
+                println!("failed to export transaction {}: {:?}", transaction.version, e);
+            }
+        }
+        if let Some(publisher) = &self.publisher {
+            let line = serde_json::json!({
+                "account": account_address,
+                "version": transaction.version,
+                "transaction_with_proof": proof,
+            });
+            publisher.publish(&line.to_string());
+        }
+    }
+
+    /// Moves the sent cursor past `transactions`, the page slice starting at sequence `start`
+    fn advance_sent_cursor(&mut self, account_address: &str, start: u64, transactions: &[TransactionView]) {
+        for (i, transaction) in transactions.iter().enumerate() {
+            self.cursors.sent_sequence.insert(account_address.to_string(), start + i as u64 + 1);
+            let dedup_window = self.dedup_window;
+            self.sent_versions.entry(account_address.to_string())
+                .or_insert_with(|| DedupWindow::new(dedup_window))
+                .insert(transaction.version);
+        }
+    }
+
+    /// Whether `version` is at least `confirmation_versions` behind the latest verified version
+    fn is_confirmed(&self, version: u64) -> bool {
+        self.confirmation_versions == 0 || self.trusted_state.as_ref()
+            .map_or(false, |state| version.saturating_add(self.confirmation_versions) <= state.latest_version())
+    }
+
+    /// The skip reason of a transaction that failed on chain, with `skip_failed_txns` only
+    ///
+    /// A failed transaction still has a valid proof, so it's submitted like any other by default.
+    fn failed_on_chain(&self, transaction: &TransactionView) -> Option<String> {
+        match &transaction.vm_status {
+            VMStatusView::Executed => None,
+            _ if !self.skip_failed_txns => None,
+            status => Some(format!("failed on chain: {:?}, gas used: {}", status, transaction.gas_used)),
+        }
+    }
+
+    /// Skips a transaction whose account state every endpoint pruned, keeping it in the cursors
+    /// so the gap can be backfilled from an archive node
+    fn record_pruned(&mut self, account_address: &str, version: u64) {
+        self.record_skip(account_address, version, "account state pruned, left as a gap".to_string());
+        self.cursors.record_pruned(account_address, version);
+    }
+
+    fn record_skip(&mut self, account_address: &str, version: u64, reason: String) {
+        println!("skip transaction {}: {}", version, reason);
+        self.clear_pending(account_address, version);
+        let summary = self.summary.accounts.entry(account_address.to_string()).or_default();
+        summary.transactions_skipped += 1;
+        summary.skip_reasons.push((version, reason));
+    }
+
+    /// Submits the transaction's proof, skipping it if it can never be accepted; returns whether
+    /// it was submitted
+    ///
+    /// Transient failures are returned so the caller keeps its cursor and retries the
+    /// transaction in the next loop instead of silently dropping it.
+    async fn submit_or_skip<S: CommandSink>(
+        &mut self,
+        transaction: &TransactionView,
+        account_address: String,
+        sink: &mut S,
+    ) -> Result<bool, Error> {
+        if let Some(reason) = self.failed_on_chain(transaction) {
+            self.record_skip(&account_address, transaction.version, reason);
+            return Ok(false);
+        }
+        match self.sync_transaction_with_proof(transaction, account_address.clone(), sink).await {
+            Ok(()) => Ok(true),
+            Err(Error::VersionPruned) => {
+                self.record_pruned(&account_address, transaction.version);
+                Ok(false)
+            }
+            Err(e) if !e.is_transient() => {
+                self.record_skip(&account_address, transaction.version, format!("permanently rejected: {:?}", e));
+                Ok(false)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn sync_transaction_with_proof<S: CommandSink>(
+        &mut self,
+        transaction: &TransactionView,
+        account_address: String,
+        sink: &mut S,
+    ) -> Result<(), Error> {
+        match self.get_transaction_proof(account_address.clone(), &transaction) {
+            Ok(transaction_with_proof) => {
+                if !self.quiet {
+                    println!("transaction_with_proof:{:?}", transaction_with_proof);
+                    for event in transaction_with_proof.events() {
+                        println!("event: key {}, seq {}, data {:?}", event.key.0, event.sequence_number, event.data);
+                    }
+                    println!("vm status: {:?}, gas used: {}", transaction_with_proof.vm_status(), transaction_with_proof.gas_used());
+                }
+                if self.is_replay(&account_address, transaction) {
+                    println!("replay after cursor reset: account {}, version {}", account_address, transaction.version);
+                }
+
+                let transaction_with_proof_b64 = encode_b64_bcs(&transaction_with_proof)?;
+                let command_value = serde_json::to_value(&CommandReqData::VerifyTransaction { account_address: account_address.clone(), transaction_with_proof_b64 })?;
+                self.push_command(command_value.to_string(), sink).await?;
+                self.emit_verified(&account_address, transaction, &transaction_with_proof);
+                self.summary.accounts.entry(account_address).or_default().transactions_processed += 1;
+                self.stats.transactions_submitted += 1;
+                Ok(())
+            }
+            Err(e) => {
+                println!("get_transaction_proof error: {:?}", e);
+                Err(e)
+            }
+        }
+    }
+
+    /// Runs one full sync pass: refreshes the trusted state, syncs every account in `addresses`
+    /// and relays the contract's signed transactions to Diem.
+    ///
+    /// A failing account is recorded in the summary and doesn't stop the others, unless
+    /// `fail_fast` is set. Cursors are only persisted as transactions are submitted, call
+    /// `save_cursors` after the pass to flush the rest.
+    pub async fn sync_once<P: PRuntime, S: CommandSink>(
+        &mut self,
+        pr: &P,
+        sink: &mut S,
+        addresses: Vec<String>,
+        start_seq: &mut u64,
+    ) -> Result<SyncSummary, Error> {
+        self.summary = SyncSummary::default();
+        self.pending.clear();
+        self.handled.clear();
+        self.init_state(Some(pr), sink, false).await?;
+
+        for addr in addresses {
+            println!("sync account: {:}", addr);
+            // Cursors advance as each transaction is submitted, so a timed out account resumes
+            // from where it stopped on the next pass
+            let result = match self.sync_timeout_secs {
+                Some(secs) => {
+                    // Every call of the account's pass draws its timeout from the same deadline,
+                    // rpc calls as their http timeout. The outer timeout can't interrupt those
+                    // blocking calls, it only fires at the next await point
+                    self.deadline = Deadline::after(Duration::from_secs(secs));
+                    tokio::time::timeout(
+                        Duration::from_secs(secs),
+                        self.sync_account(pr, addr.clone(), sink),
+                    ).await.unwrap_or(Err(Error::DeadlineExceeded))
+                }
+                None => self.sync_account(pr, addr.clone(), sink).await,
+            };
+            self.deadline = Deadline::none();
+            let pending_versions = self.pending_versions(&addr);
+            let total_failures = self.account_failures.entry(addr.clone()).or_insert(0);
+            let account_summary = self.summary.accounts.entry(addr.clone()).or_default();
+            account_summary.pending_versions = pending_versions;
+            if let Err(e) = result {
+                println!("sync account {} error: {:?}", addr, e);
+                *total_failures += 1;
+                account_summary.total_failures = *total_failures;
+                account_summary.error = Some(format!("{:?}", e));
+                if self.fail_fast {
+                    return Err(e);
+                }
+            } else {
+                account_summary.total_failures = *total_failures;
+            }
+        }
+
+        if !self.dry_run {
+            if let Err(e) = self.maybe_submit_signed_transaction(pr, start_seq).await {
+                println!("submit signed transactions error: {:?}", e);
+            }
+        }
+
+        self.summary.verified_version = self.trusted_state.as_ref().map_or(0, |s| s.latest_version());
+        self.summary.worker_stats = pr.worker_stats();
+        self.record_sync_lag();
+        self.stats.last_verified_version = self.summary.verified_version;
+        self.stats.last_success_secs = Some(self.clock.now_secs());
+        Ok(self.summary.clone())
+    }
+
+    /// Measures how far the trusted tip is ahead of the highest version processed
+    ///
+    /// A pass where every account synced and nothing is left pending processed everything up to
+    /// the confirmed tip; otherwise only the versions actually submitted or skipped count, so
+    /// repeated failures show up as a growing lag rather than being masked by a fresh ledger info.
+    fn record_sync_lag(&mut self) {
+        let tip = self.summary.verified_version;
+        let caught_up = self.summary.accounts.values().all(|a| a.error.is_none())
+            && self.pending.values().all(BTreeSet::is_empty);
+        if caught_up {
+            self.processed_version = self.processed_version.max(Some(tip.saturating_sub(self.confirmation_versions)));
+        }
+        let lag = tip.saturating_sub(self.processed_version.unwrap_or(tip));
+        self.summary.sync_lag = lag;
+        self.summary.sync_lag_ema = self.lag_ema.update(lag as f64);
+    }
+
+    async fn push_command<S: CommandSink>(
+        &mut self,
+        payload: String,
+        sink: &mut S,
+    ) -> Result<(), Error> {
+        let command_payload = serde_json::to_string(&Payload::Plain(payload))?;
+        if !self.quiet {
+            println!("command_payload:{}", command_payload);
+        }
+        if self.dry_run {
+            println!("dry run, command not submitted");
+            return Ok(());
+        }
+        sink.push_command(DIEM_CONTRACT_ID, command_payload.into_bytes()).await
+    }
+
+    /// Verifies a `TransactionWithProof`, possibly produced by another relay, against the trusted
+    /// state without issuing any rpc: the embedded ledger info must ratchet from the trusted state,
+    /// and the transaction must be proven into the state of `account` it carries
+    pub fn verify_transaction_with_proof(
+        &self,
+        account: AccountAddress,
+        proof: &TransactionWithProof,
+    ) -> Result<(), Error> {
+        self.verification_report(account, proof)?.into_result(self.strict)
+    }
+
+    /// Runs every check of `verify_transaction_with_proof`, collecting the anomalies instead of
+    /// stopping at the first one; only a ledger info that can't be trusted at all is an `Err`
+    pub fn verification_report(
+        &self,
+        expected_account: AccountAddress,
+        proof: &TransactionWithProof,
+    ) -> Result<VerificationReport, Error> {
+        let trusted_state = self.trusted_state.as_ref().ok_or(Error::StateNotInitialized)?;
+        let li = &proof.ledger_info_with_signatures;
+        trusted_state
+            .verify_and_ratchet(li, &proof.epoch_change_proof)
+            .map_err(|e| classify_ratchet_error(e, li.ledger_info().version(), trusted_state.latest_version()))?;
+
+        let mut report = VerificationReport::new(proof.version);
+        if proof.epoch_change_proof.ledger_info_with_sigs.is_empty() {
+            report.issues.push(VerificationIssue::EmptyEpochChangeProof);
+        }
+        if proof.ledger_info_to_transaction_info_proof.siblings().is_empty() {
+            report.issues.push(VerificationIssue::EmptyAccumulatorProof);
+        }
+
+        match bcs::from_bytes::<Transaction>(&proof.transaction_bytes) {
+            Ok(transaction) if transaction.hash() != proof.transaction_info.transaction_hash() => {
+                report.issues.push(VerificationIssue::BadTransactionHash);
+            }
+            Ok(_) => (),
+            Err(_) => report.issues.push(VerificationIssue::UndecodableTransaction),
+        }
+
+        // The proof doesn't carry the account address, recover it from the account's event handles
+        let account_resource = match AccountResource::try_from(&proof.account_state_blob) {
+            Ok(account_resource) => account_resource,
+            Err(_) => {
+                report.issues.push(VerificationIssue::MissingAccountResource);
+                return Ok(report);
+            }
+        };
+        let account = account_resource.received_events().key().get_creator_address();
+        // A valid proof of another account's state must not stand in for the one asked for
+        if account != expected_account {
+            report.issues.push(VerificationIssue::AccountMismatch {
+                expected: expected_account.to_string(),
+                actual: account.to_string(),
+            });
+            return Ok(report);
+        }
+
+        let account_state_proof = AccountStateProof::new(
+            TransactionInfoWithProof::new(
+                proof.ledger_info_to_transaction_info_proof.clone(),
+                proof.transaction_info.clone(),
+            ),
+            proof.transaction_info_to_account_proof.clone(),
+        );
+        if let Err(e) = account_state_proof
+            .verify(li.ledger_info(), proof.version, account.hash(), Some(&proof.account_state_blob)) {
+            report.issues.push(VerificationIssue::AccountStateProofRejected(e.to_string()));
+        }
+        Ok(report)
+    }
+
+    /// Fetches the transaction at `version` and the proof of `address`'s state at that version,
+    /// and verifies them against the trusted state, ratcheted to the latest ledger info first
+    ///
+    /// Nothing is submitted to pRuntime nor written to the export or publish sinks, the bundle is
+    /// left to the caller.
+    pub fn build_verified_bundle(&mut self, address: &str, version: u64) -> Result<TransactionWithProof, Error> {
+        let account = AccountAddress::from_hex_literal(&format!("0x{}", address.trim_start_matches("0x")))
+            .map_err(|_| Error::InvalidConfig(format!("bad account address: {}", address)))?;
+        self.refresh_trusted_state()?;
+        let transaction = self.get_transaction_by_version(version)?;
+        self.prove_transaction(account, &transaction)
+    }
+
+    fn get_transaction_proof(
+        &mut self,
+        account_address: String,
+        transaction: &TransactionView,
+    ) -> Result<TransactionWithProof, Error> {
+        // Bail out before issuing any rpc if init_state/sync_account haven't populated the state
+        let account = match self.account.get(&account_address) {
+            Some(account) => account.address,
+            None => {
+                println!("State is not initialized for account {}", account_address);
+                return Err(Error::StateNotInitialized);
+            }
+        };
+        self.prove_transaction(account, transaction)
+    }
+
+    /// Fetches the proof of `account`'s state at the version of `transaction` and verifies the
+    /// transaction with it
+    fn prove_transaction(
+        &mut self,
+        account: AccountAddress,
+        transaction: &TransactionView,
+    ) -> Result<TransactionWithProof, Error> {
+        let (trusted_version, latest_li, epoch_change_proof) = match (
+            self.trusted_state.as_ref(),
+            self.latest_li.clone(),
+            self.epoch_change_proof.clone(),
+        ) {
+            (Some(trusted_state), Some(latest_li), Some(epoch_change_proof)) => {
+                (trusted_state.latest_version(), latest_li, epoch_change_proof)
+            }
+            _ => {
+                println!("State is not initialized for account {}", account);
+                return Err(Error::StateNotInitialized);
+            }
+        };
+
+        let mut batch = JsonRpcBatch::new();
+        batch.add_get_account_state_with_proof_request(
+            account,
+            Some(transaction.version),
+            Some(trusted_version));
+        let resp = match self.request_historical_rpc(batch) {
+            Ok(resp) => resp,
+            Err(Error::VersionPruned) => {
+                println!("Account state at version {} is pruned on every endpoint", transaction.version);
+                return Err(Error::VersionPruned);
+            }
+            Err(_) => {
+                println!("Failed to get account's state with proof");
+                return Err(Error::FailedToGetResponse);
+            }
+        };
+        let account_state_proof =
+            AccountStateWithProofView::from_response(resp.clone()).map_err(|_| Error::FailedToDecode)?;
+
+        let ledger_info_to_transaction_info_proof: TransactionAccumulatorProof =
+            decode_bcs_hex(
+                account_state_proof.proof.ledger_info_to_transaction_info_proof,
+                "ledger_info_to_transaction_info_proof",
+                self.max_proof_bytes,
+            )?;
+        let transaction_info: TransactionInfo =
+            decode_bcs_hex(account_state_proof.proof.transaction_info, "transaction_info", self.max_proof_bytes)?;
+        let transaction_info_to_account_proof: SparseMerkleProof =
+            decode_bcs_hex(
+                account_state_proof.proof.transaction_info_to_account_proof,
+                "transaction_info_to_account_proof",
+                self.max_proof_bytes,
+            )?;
+        let account_state_blob: AccountStateBlob =
+            decode_bcs_hex(
+                account_state_proof.blob.ok_or(Error::FailedToDecode)?,
+                "account_state_blob",
+                self.max_proof_bytes,
+            )?;
+        if transaction_info.transaction_hash().to_hex() != transaction.hash {
+            println!("Bad transaction hash");
+            return Err(Error::BadTransactionHash);
+        }
+
+        let state_proof = TransactionWithProof {
+            transaction_bytes: transaction.bytes.clone().into_bytes().map_err(|_| Error::FailedToDecode)?,
+            epoch_change_proof,
+            ledger_info_with_signatures: latest_li,
+            ledger_info_to_transaction_info_proof,
+            transaction_info,
+            transaction_info_to_account_proof,
+            account_state_blob,
+            version: transaction.version,
+            events: transaction.events.clone(),
+            vm_status: Some(transaction.vm_status.clone()),
+            gas_used: transaction.gas_used,
+        };
+        let report = if self.log_verify_timing {
+            let start = Instant::now();
+            let report = self.verification_report(account, &state_proof)?;
+            let elapsed = start.elapsed();
+            self.summary.verify_latency.observe(elapsed);
+            println!("Transaction {} was verified in {}us", transaction.version, elapsed.as_micros());
+            report
+        } else {
+            self.verification_report(account, &state_proof)?
+        };
+        if !report.is_clean() {
+            println!("verification report: {:?}", report);
+            self.summary.verification_reports.push(report.clone());
+        }
+        report.into_result(self.strict)?;
+        if !self.quiet {
+            println!("Transaction was verified");
+        }
+
+        Ok(state_proof)
+    }
+
+    /// Fetches `limit` transactions starting at `start` in a single rpc and caches them
+    fn get_transactions_range(
+        &mut self,
+        start: u64,
+        limit: u64,
+    ) -> Result<Vec<TransactionView>, Error> {
+        let mut batch = JsonRpcBatch::new();
+        batch.add_get_transactions_request(start, limit, self.include_events);
+        let resp = self.request_rpc(batch).map_err(|_| Error::FailedToGetTransaction)?;
+        let transactions = TransactionView::vec_from_response(resp).map_err(|_| Error::FailedToDecode)?;
+
+        for transaction in &transactions {
+            self.tx_cache.insert(transaction.clone());
+        }
+        Ok(transactions)
+    }
+
+    fn get_transaction_by_version(
+        &mut self,
+        version: u64
+    ) -> Result<TransactionView, Error> {
+        if let Some(transaction) = self.tx_cache.get(version) {
+            return Ok(transaction);
+        }
+        self.get_transactions_range(version, self.fetch_window)?;
+        self.tx_cache.get(version).ok_or(Error::NoTransaction)
+    }
+
+    fn request_rpc(
+        &mut self,
+        batch: JsonRpcBatch
+    ) -> Result<JsonRpcResponse, Error> {
+        self.request_rpc_from(&self.rpc, batch)
+    }
+
+    /// Requests state at a past version, falling back to the archive endpoint if the rpc
+    /// endpoint pruned it
+    fn request_historical_rpc(
+        &mut self,
+        batch: JsonRpcBatch
+    ) -> Result<JsonRpcResponse, Error> {
+        match (self.request_rpc(batch.clone()), &self.archive) {
+            (Err(Error::VersionPruned), Some(archive)) => {
+                println!("version pruned by the rpc endpoint, asking the archive endpoint");
+                self.request_rpc_from(archive, batch)
+            }
+            (result, _) => result,
+        }
+    }
+
+    /// Executes `batch` against `endpoint`, or the replayed cassette, recording the exchange
+    /// when a cassette is recording
+    fn execute_batch(
+        &self,
+        endpoint: &RpcEndpoint,
+        batch: &JsonRpcBatch,
+        timeout: Duration,
+    ) -> Result<Vec<Result<JsonRpcResponse>>> {
+        let responses = match &self.cassette {
+            Some(cassette) if cassette.is_replay() => cassette.play(&endpoint.url, batch)?,
+            _ => endpoint.client.execute_raw(batch, timeout)?,
+        };
+        ensure!(
+            responses.len() == batch.requests.len(),
+            "expected {} rpc responses, got {}",
+            batch.requests.len(),
+            responses.len()
+        );
+        if let Some(cassette) = &self.cassette {
+            if let Err(e) = cassette.save(&endpoint.url, batch, &responses) {
+                println!("failed to record rpc exchange: {:?}", e);
+            }
+        }
+        if let Some(version) = responses.first()
+            .and_then(|response| response.get("diem_ledger_version"))
+            .and_then(serde_json::Value::as_u64) {
+            self.chain_version.fetch_max(version, Ordering::Relaxed);
+        }
+        process_batch_response(batch.clone(), responses)
+    }
+
+    fn request_rpc_from(
+        &self,
+        endpoint: &RpcEndpoint,
+        batch: JsonRpcBatch
+    ) -> Result<JsonRpcResponse, Error> {
+        let mut attempt = 0;
+        loop {
+            let timeout = self.deadline.call_timeout(self.call_timeout)?;
+            // The batch was answered, only without a response for the request
+            let mut missing = false;
+            // The http request ran out of the time it was given
+            let mut timed_out = false;
+            // The node pruned the requested version
+            let mut pruned = false;
+            let retry = match self.execute_batch(endpoint, &batch, timeout) {
+                Ok(responses) => {
+                    if !self.quiet {
+                        println!("rpc responses：{:?}\n", responses);
+                    }
+                    match get_response_from_batch(0, &responses) {
+                        Ok(Ok(resp)) => return Ok(resp.clone()),
+                        Ok(Err(e)) => match e.downcast_ref::<JsonRpcError>() {
+                            Some(rpc_error) if is_pruned_error(rpc_error) => {
+                                pruned = true;
+                                self.retry_policy.should_retry(&RpcFailure::Pruned(rpc_error))
+                            }
+                            Some(rpc_error) => self.retry_policy.should_retry(&RpcFailure::Rpc(rpc_error)),
+                            None => {
+                                missing = true;
+                                self.retry_policy.should_retry(&RpcFailure::MissingResponse)
+                            }
+                        },
+                        Err(_) => {
+                            missing = true;
+                            self.retry_policy.should_retry(&RpcFailure::MissingResponse)
+                        }
+                    }
+                }
+                Err(e) => {
+                    println!("rpc request failed: {:?}", e);
+                    timed_out = e.downcast_ref::<reqwest::Error>().map_or(false, |e| e.is_timeout());
+                    self.retry_policy.should_retry(&RpcFailure::Transport(&e))
+                }
+            };
+            self.rpc_errors.fetch_add(1, Ordering::Relaxed);
+            let error = if timed_out {
+                Error::DeadlineExceeded
+            } else if pruned {
+                Error::VersionPruned
+            } else if missing {
+                Error::MissingRpcResponse
+            } else {
+                Error::FailedToGetResponse
+            };
+            if !retry || attempt >= self.rpc_retries {
+                return Err(error);
+            }
+            if !self.retry_budget.lock().unwrap().try_acquire(self.clock.now()) {
+                println!("retry budget exhausted, not retrying rpc request");
+                return Err(error);
+            }
+            attempt += 1;
+            // Backing off leaves a struggling node room to recover, within the account's deadline
+            let backoff = self.deadline.call_timeout(self.retry_policy.backoff(attempt))?;
+            println!("retrying rpc request in {:?}, attempt {}", backoff, attempt);
+            std::thread::sleep(backoff);
+        }
+    }
+
+    async fn maybe_submit_signed_transaction<P: PRuntime>(
+        &mut self,
+        pr: &P,
+        start_seq: &mut u64,
+    ) -> Result<(), Error> {
+        let request = QueryReqData::GetSignedTransactions { start: *start_seq };
+        let resp = self.deadline.run(self.call_timeout, pr.query(DIEM_CONTRACT_ID, request)).await?;
+        println!("query signed transaction resp:{:?}", resp);
+        if let QueryRespData::GetSignedTransactions { queue_b64 } = resp {
+            // The queue comes from contract state, malformed data must not take the bridge down
+            let data = base64::decode(&queue_b64)
+                .map_err(|_| Error::MalformedProofData { field: "signed_transactions_queue" })?;
+            let transaction_data: Vec<TransactionData> = Decode::decode(&mut &data[..])
+                .map_err(|_| Error::MalformedProofData { field: "signed_transactions_queue" })?;
+            for td in &transaction_data {
+                println!("transaction data:{:?}", td);
+                let signed_tx: SignedTransaction = bcs::from_bytes(&td.signed_tx)
+                    .map_err(|_| Error::MalformedProofData { field: "signed_tx" })?;
+                println!("signed transaction:{:?}", signed_tx);
+                let mut batch = JsonRpcBatch::new();
+                let _ = batch.add_submit_request(signed_tx);
+                match self.request_rpc(batch) {
+                    Ok(_) => {
+                        let receiver_address = hex::encode_upper(td.address.clone());
+                        println!("submit transaction for {:?}", receiver_address);
+
+                        if td.new_account && !self.address.contains(&receiver_address) {
+                            self.address.push(receiver_address);
+                        }
+
+                        if td.sequence > *start_seq {
+                            *start_seq = td.sequence
+                        }
+                    }
+                    Err(_) => {
+                        println!("request rpc error");
+                    }
+                }
+
+            }
+            if transaction_data.len() > 0 {
+                *start_seq = *start_seq + 1;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Checks that the last bytes of an account's authentication key are its address, which holds
+/// until the key is rotated
+fn check_auth_key(address: AccountAddress, authentication_key: Option<&[u8]>) -> Result<(), Error> {
+    let authentication_key = match authentication_key {
+        Some(key) => key,
+        None => return Ok(()),
+    };
+    let derived = authentication_key.len().checked_sub(AccountAddress::LENGTH)
+        .map(|start| &authentication_key[start..]);
+    if derived == Some(&address.to_vec()[..]) {
+        Ok(())
+    } else {
+        Err(Error::AuthKeyAddressMismatch {
+            address: address.to_string(),
+            authentication_key: hex::encode(authentication_key),
+        })
+    }
+}
+
+/// Checks that `latest_li` is in the epoch the trusted state ratcheted to with
+/// `latest_epoch_change_li`, or is that epoch change itself
+///
+/// The transaction proofs are verified against `latest_li` under the trusted validator set, so a
+/// ledger info of another epoch must never be kept.
+fn check_ledger_state(
+    latest_li: &LedgerInfoWithSignatures,
+    latest_epoch_change_li: Option<&LedgerInfoWithSignatures>,
+) -> Result<(), Error> {
+    let trusted_epoch = latest_epoch_change_li.ok_or(Error::StateNotInitialized)?.ledger_info().next_block_epoch();
+    let li = latest_li.ledger_info();
+    if li.epoch() == trusted_epoch || (li.ends_epoch() && li.next_block_epoch() == trusted_epoch) {
+        Ok(())
+    } else {
+        println!("Latest ledger info is in epoch {}, trusted state in epoch {}", li.epoch(), trusted_epoch);
+        Err(Error::InconsistentLedgerState { li_epoch: li.epoch(), trusted_epoch })
+    }
+}
+
+/// Appends the epoch changes of `delta` that `known` doesn't cover yet
+fn extend_epoch_change_proof(known: EpochChangeProof, delta: EpochChangeProof) -> EpochChangeProof {
+    let mut ledger_infos = known.ledger_info_with_sigs;
+    let last_epoch = ledger_infos.last().map(|li| li.ledger_info().epoch());
+    ledger_infos.extend(delta.ledger_info_with_sigs.into_iter()
+        .filter(|li| last_epoch.map_or(true, |epoch| li.ledger_info().epoch() > epoch)));
+    EpochChangeProof::new(ledger_infos, false)
+}
+
+/// Maps a `verify_and_ratchet` failure to an `Error` the caller can decide to retry on
+fn classify_ratchet_error(error: anyhow::Error, version: u64, known_version: u64) -> Error {
+    if error.downcast_ref::<VerifyError>().is_some() {
+        Error::StateProofSignatureFailure(error.to_string())
+    } else if version < known_version {
+        Error::StaleStateProof { version, known_version }
+    } else {
+        Error::InvalidStateProof(error.to_string())
+    }
+}
+
+/// Read-only: ratchets to the latest epoch without touching substrate or pRuntime
+fn print_validators(args: &Args) -> Result<(), Error> {
+    let mut diem = DiemBridgeBuilder::new(&args.diem_rpc_endpoint)
+        .chain_id(args.chain_id.unwrap_or(ChainIdSetting::FromUrl))
+        .max_proof_bytes(args.max_proof_bytes)
+        .rpc_retries(args.rpc_retries)
+        .build()?;
+    diem.refresh_trusted_state()?;
+
+    let epoch_state = diem.validator_set().ok_or(Error::StateNotInitialized)?;
+    let verifier = &epoch_state.verifier;
+    println!("epoch: {}", epoch_state.epoch);
+    println!("quorum voting power: {}", verifier.quorum_voting_power());
+    for address in verifier.get_ordered_account_addresses_iter() {
+        println!(
+            "{} voting power: {}, consensus public key: {}",
+            address,
+            verifier.get_voting_power(&address).unwrap_or(0),
+            verifier.get_public_key(&address).map_or(String::new(), |key| hex::encode(key.to_bytes())),
+        );
+    }
+    Ok(())
+}
+
+fn print_verified_bundle(args: &Args, account: &str, version: u64) -> Result<(), Error> {
+    let mut diem = DiemBridgeBuilder::new(&args.diem_rpc_endpoint)
+        .chain_id(args.chain_id.unwrap_or(ChainIdSetting::FromUrl))
+        .max_proof_bytes(args.max_proof_bytes)
+        .rpc_retries(args.rpc_retries)
+        .archive_endpoint(args.archive_endpoint.clone())
+        .build()?;
+    let bundle = diem.build_verified_bundle(account, version)?;
+    println!("{}", encode_b64_bcs(&bundle)?);
+    Ok(())
+}
+
+/// The waypoint of the epoch change ledger info at `version` in `epoch_change_proof`, if any
+fn epoch_boundary_waypoint(epoch_change_proof: &EpochChangeProof, version: u64) -> Option<Waypoint> {
+    epoch_change_proof.ledger_info_with_sigs.iter()
+        .find(|li| li.ledger_info().version() == version)
+        .and_then(|li| Waypoint::new_epoch_boundary(li.ledger_info()).ok())
+}
+
+/// The first of `waypoints` that is an epoch boundary of `epoch_change_proof` past `version`
+fn matching_waypoint<'a>(
+    waypoints: &'a [Waypoint],
+    epoch_change_proof: &EpochChangeProof,
+    version: u64,
+) -> Option<&'a Waypoint> {
+    waypoints.iter().find(|waypoint| waypoint.version() > version
+        && epoch_boundary_waypoint(epoch_change_proof, waypoint.version()).as_ref() == Some(*waypoint))
+}
+
+/// Read-only: fails with `WaypointMismatch` unless `waypoint` is an epoch boundary of the chain
+fn check_waypoint(args: &Args, waypoint: &Waypoint) -> Result<(), Error> {
+    let mut diem = DiemBridgeBuilder::new(&args.diem_rpc_endpoint)
+        .chain_id(args.chain_id.unwrap_or(ChainIdSetting::FromUrl))
+        .max_proof_bytes(args.max_proof_bytes)
+        .rpc_retries(args.rpc_retries)
+        .build()?;
+    let (_, _, epoch_change_proof) = diem.refresh_trusted_state()?;
+
+    let actual = epoch_boundary_waypoint(&epoch_change_proof, waypoint.version());
+    let actual = actual.map_or_else(|| format!("no epoch change at version {}", waypoint.version()), |w| w.to_string());
+    println!("expected waypoint: {}", waypoint);
+    println!("node waypoint:     {}", actual);
+    if actual != waypoint.to_string() {
+        return Err(Error::WaypointMismatch { expected: waypoint.to_string(), actual });
+    }
+    println!("waypoint matches");
+    Ok(())
+}
+
+/// Everything a sync pass needs, set up from the command line arguments
+struct Session {
+    diem: DiemBridge,
+    sink: ChainSink,
+    pr: PRuntimePool<PrClient>,
+    start_seq: u64,
+    /// Addresses read from `--accounts-file`, already merged into `diem.address`
+    file_accounts: Vec<String>,
+}
+
+/// Connects to substrate and the pRuntime workers, then initializes the trusted state
+async fn connect(args: &Args, clock: Arc<dyn Clock>) -> Result<Session, Error> {
+    let file_accounts = match &args.accounts_file {
+        Some(path) => read_accounts_file(path)?,
+        None => vec![],
+    };
+    let mut diem = DiemBridgeBuilder::new(&args.diem_rpc_endpoint)
+        .clock(clock.clone())
+        .chain_id(args.chain_id.unwrap_or(ChainIdSetting::FromUrl))
+        .state_file(args.state_file.clone())
+        .verify_only_new(args.verify_only_new)
+        .include_system_txns(args.include_system_txns)
+        .max_ledger_info_age_secs(args.max_ledger_info_age_secs)
+        .max_proof_bytes(args.max_proof_bytes)
+        .fail_fast(args.fail_fast)
+        .reset_cursor(args.reset_cursor, args.confirm_reset)
+        .dry_run(args.dry_run)
+        .quiet(matches!(args.command, Some(Command::Watch)))
+        .dedup_window(args.dedup_window)
+        .fetch_window(args.fetch_window)
+        .tx_cache_size(args.tx_cache_size)
+        .log_verify_timing(args.log_verify_timing)
+        .sync_timeout_secs(args.sync_timeout_secs)
+        .strict(args.strict)
+        .include_events(!args.no_inline_events)
+        .rpc_retries(args.rpc_retries)
+        .lag_ema_alpha(args.lag_ema_alpha)
+        .archive_endpoint(args.archive_endpoint.clone())
+        .retry_budget(args.retry_budget, args.retry_budget_refill)
+        .batch_proofs(args.batch_proofs)
+        .call_timeout_secs(args.call_timeout_secs)
+        .skip_failed_txns(args.skip_failed_txns)
+        .record_rpc(args.record_rpc.clone())
+        .replay_rpc(args.replay_rpc.clone())
+        .export(args.export_file.clone(), args.export_format)
+        .waypoints(args.waypoints.clone())
+        .publish_uds(args.publish_uds.clone())
+        .confirmation_versions(args.confirmation_versions)
+        .verify_balances(args.verify_balances)
+        .rpc_pool_max_idle(args.rpc_pool_max_idle)
+        .rpc_http2(args.rpc_http2)
+        .build()?;
+    diem.load_trusted_state();
+    if let Some(path) = &args.import_snapshot {
+        diem.import_snapshot(&std::fs::read(path)?)?;
+        println!("Imported snapshot {}", path);
+    }
+    let client = subxt::ClientBuilder::<Runtime>::new()
+        .skip_type_sizes_check()
+        .set_url(args.substrate_ws_endpoint.clone())
+        .build().await?;
+    println!("Connected to substrate at: {}", args.substrate_ws_endpoint.clone());
+
+    let pair = <sr25519::Pair as Pair>::from_string(&args.mnemonic, None)
+        .expect("Bad privkey derive path");
+    let mut sink = ChainSink::new(client, subxt::PairSigner::new(pair));
+
+    let workers = args.pruntime_endpoint.iter()
+        .map(|endpoint| PrClient::new(endpoint, args.pruntime_compress, args.pruntime_header.clone())
+            .with_encoding(args.pruntime_encoding)
+            .with_max_response_bytes(args.pruntime_max_response_bytes))
+        .collect();
+    let pr = PRuntimePool::new(workers, args.pruntime_mode, args.pruntime_quorum)?;
+    let resp = pr.query(DIEM_CONTRACT_ID, QueryReqData::CurrentState).await?;
+    let state = match resp {
+        QueryRespData::CurrentState { state } => state,
+        _ => {
+            println!("query state error");
+            return Err(Error::FailedToInitState);
+        }
+    };
+    println!("current state: {:?}", state);
+
+    diem.init_state(Some(&pr), &mut sink, true).await?;
+    diem.address = state.account_address;
+    for address in &file_accounts {
+        if !diem.address.contains(address) {
+            diem.address.push(address.clone());
+        }
+    }
+
+    Ok(Session { diem, sink, pr, start_seq: state.queue_seq, file_accounts })
+}
+
+/// Runs a single sync pass over the contract's accounts and returns its summary
+///
+/// Unlike `run`, never loops, so it can be awaited from tests or an embedding process.
+pub async fn run_once(args: Args) -> Result<SyncSummary, Error> {
+    let Session { mut diem, mut sink, pr, mut start_seq, .. } = connect(&args, Arc::new(SystemClock)).await?;
+    let address = diem.address.clone();
+    let summary = diem.sync_once(&pr, &mut sink, address, &mut start_seq).await?;
+    diem.save_cursors()?;
+    diem.save_trusted_state()?;
+    Ok(summary)
+}
+
+/// Runs the command selected by `args`, syncing until interrupted if there is none
+pub async fn run(args: Args) -> Result<(), Error> {
+    match &args.command {
+        Some(Command::Validators) => print_validators(&args),
+        Some(Command::CheckWaypoint { waypoint }) => check_waypoint(&args, waypoint),
+        Some(Command::Prove { account, version }) => print_verified_bundle(&args, account, *version),
+        Some(Command::Resync { account, yes }) => {
+            if wipe_cursors(&args, account.as_deref(), *yes)? {
+                bridge(args, Arc::new(SystemClock)).await
+            } else {
+                Ok(())
+            }
+        }
+        Some(Command::Watch) | None => bridge(args, Arc::new(SystemClock)).await,
+    }
+}
+
+/// Deletes the state file, or only the cursors of `account`, for `pdiem resync`
+///
+/// Returns whether the cursors were wiped and the bridge should start over: not under
+/// `--dry-run`, which only logs what would go, nor when the confirmation is declined.
+fn wipe_cursors(args: &Args, account: Option<&str>, yes: bool) -> Result<bool, Error> {
+    let state_file = args.state_file.as_deref()
+        .ok_or_else(|| Error::InvalidConfig("resync requires --state-file".to_string()))?;
+    let mut cursors = SyncCursors::load(state_file)?;
+    match account {
+        Some(account) => {
+            println!("resync: wiping the cursors of {} in {}", account, state_file);
+            if !cursors.forget(account) {
+                println!("resync: {} has no cursors in {}", account, state_file);
+            }
+        }
+        None => println!("resync: wiping {}: {:?}", state_file, cursors),
+    }
+    if args.dry_run {
+        println!("resync: dry run, {} left untouched", state_file);
+        return Ok(false);
+    }
+    if !yes {
+        eprint!("Already applied transactions will be submitted again. Wipe the cursors? [y/N] ");
+        let mut answer = String::new();
+        std::io::stdin().read_line(&mut answer)?;
+        if !matches!(answer.trim(), "y" | "Y" | "yes") {
+            println!("resync: aborted");
+            return Ok(false);
+        }
+    }
+    match account {
+        Some(_) => cursors.save(state_file)?,
+        None if std::path::Path::new(state_file).exists() => std::fs::remove_file(state_file)?,
+        None => (),
+    }
+    println!("resync: cursors wiped, syncing from scratch");
+    Ok(true)
+}
+
+async fn bridge(args: Args, clock: Arc<dyn Clock>) -> Result<(), Error> {
+    let Session { mut diem, mut sink, pr, mut start_seq, mut file_accounts } =
+        connect(&args, clock.clone()).await?;
+    let mut hangup = match &args.accounts_file {
+        Some(_) => Some(tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())?),
+        None => None,
+    };
+
+    let watchdog = Watchdog::new(clock.clone());
+    if let Some(max_sync_lag_secs) = args.max_sync_lag_secs {
+        watchdog.spawn(max_sync_lag_secs);
+    }
+    let mut dashboard = match args.command {
+        Some(Command::Watch) => Some(Dashboard::default()),
+        _ => None,
+    };
+
+    loop {
+        let address = diem.address.clone();
+        match diem.sync_once(&pr, &mut sink, address, &mut start_seq).await {
+            Ok(summary) => {
+                println!("sync summary: {:?}", summary);
+                println!("bridge stats: {:?}", diem.stats_snapshot());
+                if args.strict && !summary.verification_reports.is_empty() {
+                    diem.save_cursors()?;
+                    return Err(Error::StrictVerificationFailure(format!("{:?}", summary.verification_reports)));
+                }
+                if summary.accounts.is_empty() || summary.accounts.values().any(|a| a.error.is_none()) {
+                    watchdog.feed();
+                }
+                if let Some(dashboard) = dashboard.as_mut() {
+                    dashboard.update(&summary, &diem.stats_snapshot(), &diem.cursors().sent_sequence);
+                    dashboard.render();
+                }
+            }
+            Err(e @ Error::InvalidStateProof(_))
+            | Err(e @ Error::StateProofSignatureFailure(_))
+            | Err(e @ Error::StaleLedgerInfo { .. })
+            | Err(e @ Error::InconsistentLedgerState { .. })
+            | Err(e @ Error::MalformedProofData { .. }) => {
+                println!("Rejected state proof from endpoint: {:?}, retry in next loop", e);
+                if let Some(dashboard) = dashboard.as_mut() {
+                    dashboard.record_error(format!("{:?}", e));
+                    dashboard.render();
+                }
+            }
+            Err(e) => return Err(e),
+        }
+
+        if let Err(e) = diem.save_cursors() {
+            println!("failed to save sync cursors: {:?}", e);
+        }
+        if let Err(e) = diem.save_trusted_state() {
+            println!("failed to save trusted state: {:?}", e);
+        }
+        if let Some(path) = &args.export_snapshot {
+            if let Err(e) = diem.export_snapshot().and_then(|data| write_atomic(path, &data)) {
+                println!("failed to export snapshot: {:?}", e);
+            }
+        }
+
+        println!("Waiting for next loop\n");
+        let reload_enabled = hangup.is_some();
+        tokio::select! {
+            _ = clock.sleep(std::time::Duration::from_millis(args.interval * 1000)) => (),
+            _ = async { hangup.as_mut().unwrap().recv().await }, if reload_enabled => {
+                // Only reached with --accounts-file; a bad file keeps the current accounts
+                let path = args.accounts_file.as_deref().unwrap_or_default();
+                match read_accounts_file(path) {
+                    Ok(accounts) => {
+                        let (added, removed) = diff_accounts(&file_accounts, &accounts);
+                        println!("Reloaded {}: added {:?}, removed {:?}", path, added, removed);
+                        diem.address.retain(|address| !removed.contains(address));
+                        for address in added {
+                            if !diem.address.contains(&address) {
+                                diem.address.push(address);
+                            }
+                        }
+                        file_accounts = accounts;
+                    }
+                    Err(e) => println!("Failed to reload {}: {:?}, keeping the current accounts", path, e),
+                }
+            }
+            _ = tokio::signal::ctrl_c() => {
+                println!("Shutting down, flushing sync cursors");
+                diem.save_cursors()?;
+                break;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pruntime_client::MockPRuntime;
+    use crate::mock_rpc::MockRpcServer;
+    use crate::clock::MockClock;
+    use codec::Encode;
+    use serde_json::json;
+    use std::collections::HashMap;
+    use diem_json_rpc_client::views::ScriptView;
+
+    fn metadata_server(chain_id: u8) -> MockRpcServer {
+        let mut results = HashMap::new();
+        results.insert("get_metadata".to_string(), json!({ "version": 42, "timestamp": 1_600_000_000_000_000u64, "chain_id": chain_id }));
+        results.insert("get_transactions".to_string(), json!([]));
+        MockRpcServer::start(results)
+    }
+
+    #[test]
+    fn get_chain_metadata_from_rpc() {
+        let server = metadata_server(2);
+        let mut diem = DiemBridge::new(&server.url).unwrap();
+
+        let metadata = diem.get_chain_metadata().unwrap();
+        assert_eq!(metadata.version, 42);
+        assert_eq!(metadata.chain_id, 2);
+        assert_eq!(*server.calls.lock().unwrap(), vec!["get_metadata".to_string()]);
+    }
+
+    #[test]
+    fn auto_chain_id_is_frozen_after_detection() {
+        let server = metadata_server(2);
+        let mut diem = DiemBridgeBuilder::new(&server.url).chain_id(ChainIdSetting::Auto).build().unwrap();
+
+        diem.check_chain_metadata().unwrap();
+        assert_eq!(diem.chain_id.id(), 2);
+        assert_eq!(diem.chain_id_setting, ChainIdSetting::Fixed(ChainId::new(2)));
+
+        let server = metadata_server(4);
+        let mut other = DiemBridgeBuilder::new(&server.url).chain_id(diem.chain_id_setting).build().unwrap();
+        assert!(matches!(other.check_chain_metadata(), Err(Error::ChainIdMismatch { expected: 2, actual: 4 })));
+    }
+
+    #[test]
+    fn parse_pruntime_header_validates_syntax() {
+        assert_eq!(
+            parse_pruntime_header("X-Worker-Id=worker-1").unwrap(),
+            ("X-Worker-Id".to_string(), "worker-1".to_string()),
+        );
+        assert_eq!(
+            parse_pruntime_header("Authorization=Bearer a=b").unwrap(),
+            ("Authorization".to_string(), "Bearer a=b".to_string()),
+        );
+        assert!(parse_pruntime_header("Authorization").is_err());
+        assert!(parse_pruntime_header("Bad Header=1").is_err());
+        assert!(parse_pruntime_header("X-Worker-Id=line\nbreak").is_err());
+    }
+
+    #[test]
+    fn trusted_state_is_reanchored_on_epoch_change() {
+        use diem_crypto::HashValue;
+        use diem_types::{block_info::BlockInfo, ledger_info::LedgerInfo, validator_verifier::ValidatorVerifier};
+
+        let server = MockRpcServer::start(single_transaction_chain());
+        let mut diem = DiemBridge::new(&server.url).unwrap();
+        let set_trusted_states = |commands: Vec<CommandReqData>| {
+            commands.iter().filter(|c| matches!(c, CommandReqData::SetTrustedState { .. })).count()
+        };
+
+        // Connecting anchors the contract, a loop in the same epoch doesn't
+        assert_eq!(set_trusted_states(diem.init_commands(true).unwrap()), 1);
+        assert_eq!(set_trusted_states(diem.init_commands(false).unwrap()), 0);
+
+        // Epoch 1 ends before the next loop
+        let epoch_state = EpochState { epoch: 2, verifier: ValidatorVerifier::new(BTreeMap::new()) };
+        let li = LedgerInfoWithSignatures::new(
+            LedgerInfo::new(
+                BlockInfo::new(1, 0, HashValue::zero(), HashValue::zero(), 1, 0, Some(epoch_state)),
+                HashValue::zero(),
+            ),
+            BTreeMap::new(),
+        );
+        server.set_result("get_state_proof", json!({
+            "ledger_info_with_signatures": hex::encode(bcs::to_bytes(&li).unwrap()),
+            "epoch_change_proof": hex::encode(bcs::to_bytes(&EpochChangeProof::new(vec![li.clone()], false)).unwrap()),
+            "ledger_consistency_proof": "",
+        }));
+        assert_eq!(set_trusted_states(diem.init_commands(false).unwrap()), 1);
+        assert_eq!(set_trusted_states(diem.init_commands(false).unwrap()), 0);
+    }
+
+    #[test]
+    fn account_info_carries_canonical_event_keys() {
+        use crate::encoding::decode_bcs_b64;
+        use diem_types::event::EventKey;
+
+        let sent = BytesView("AB".repeat(EventKey::LENGTH));
+        let received = BytesView("0c".repeat(EventKey::LENGTH));
+        let account_info = AccountInfo {
+            address: AccountAddress::from_hex_literal("0x1").unwrap(),
+            authentication_key: None,
+            sequence_number: 3,
+            sent_events_key: validate_event_key(&sent, "sent_events_key").unwrap(),
+            received_events_key: validate_event_key(&received, "received_events_key").unwrap(),
+            balances: vec![],
+        };
+
+        let decoded: AccountInfo = decode_bcs_b64(&encode_b64_bcs(&account_info).unwrap()).unwrap();
+        assert_eq!(decoded.sent_events_key, "ab".repeat(EventKey::LENGTH));
+        assert_eq!(decoded.received_events_key, received.0);
+        assert_eq!(decoded.sequence_number, 3);
+
+        // The contract reads the keys as BCS strings: uleb128 length then the lowercase hex
+        let bytes = bcs::to_bytes(&account_info).unwrap();
+        let key_hex = "ab".repeat(EventKey::LENGTH);
+        let mut expected = vec![key_hex.len() as u8];
+        expected.extend_from_slice(key_hex.as_bytes());
+        assert!(bytes.windows(expected.len()).any(|w| w == &expected[..]));
+    }
+
+    // Golden encodings the contract parses, any change here breaks the contract interface
+    const TRANSACTION_WITH_PROOF_HEX: [&str; 7] = [
+        "02aabb000000010000000000000002000000000000002003030303030303030303030303030303030303030303030303",
+        "030303030303032004040404040404040404040404040404040404040404040404040404040404040500000000000000",
+        "060000000000000000200707070707070707070707070707070707070707070707070707070707070707000120080808",
+        "080808080808080808080808080808080808080808080808080808080820090909090909090909090909090909090909",
+        "0909090909090909090909090909200a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a20",
+        "0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0c0000000000000000000001cc0d0000",
+        "0000000000",
+    ];
+    const ACCOUNT_INFO_HEX: [&str; 3] = [
+        "000000000000000000000000000000010003000000000000003061626162616261626162616261626162616261626162",
+        "616261626162616261626162616261626162616261626162616230306330633063306330633063306330633063306330",
+        "633063306330633063306330633063306330633063306330633063010a0000000000000003585553",
+    ];
+
+    #[test]
+    fn transaction_with_proof_wire_format() {
+        use diem_crypto::HashValue;
+        use diem_types::{block_info::BlockInfo, ledger_info::LedgerInfo, vm_status::KeptVMStatus};
+
+        let hash = |b| HashValue::new([b; HashValue::LENGTH]);
+        let proof = TransactionWithProof {
+            transaction_bytes: vec![0xaa, 0xbb],
+            epoch_change_proof: EpochChangeProof::new(vec![], false),
+            ledger_info_with_signatures: LedgerInfoWithSignatures::new(
+                LedgerInfo::new(BlockInfo::new(1, 2, hash(3), hash(4), 5, 6, None), hash(7)),
+                BTreeMap::new(),
+            ),
+            ledger_info_to_transaction_info_proof: TransactionAccumulatorProof::new(vec![hash(8)]),
+            transaction_info: TransactionInfo::new(hash(9), hash(10), hash(11), 12, KeptVMStatus::Executed),
+            transaction_info_to_account_proof: SparseMerkleProof::new(None, vec![]),
+            account_state_blob: AccountStateBlob::from(vec![0xcc]),
+            version: 13,
+            events: vec![],
+            vm_status: None,
+            gas_used: 0,
+        };
+
+        let bytes = bcs::to_bytes(&proof).unwrap();
+        assert_eq!(hex::encode(&bytes), TRANSACTION_WITH_PROOF_HEX.concat());
+        assert_eq!(encode_b64_bcs(&proof).unwrap(), base64::encode(&bytes));
+    }
+
+    #[test]
+    fn account_info_wire_format() {
+        let account_info = AccountInfo {
+            address: AccountAddress::from_hex_literal("0x1").unwrap(),
+            authentication_key: None,
+            sequence_number: 3,
+            sent_events_key: "ab".repeat(24),
+            received_events_key: "0c".repeat(24),
+            balances: vec![Amount { amount: 10, currency: "XUS".to_string() }],
+        };
+
+        assert_eq!(hex::encode(bcs::to_bytes(&account_info).unwrap()), ACCOUNT_INFO_HEX.concat());
+        assert_eq!(encode_b64_bcs(&account_info).unwrap(), "AAAAAAAAAAAAAAAAAAAAAQADAAAAAAAAADBhYmFiYWJhYmFiYWJhYmFiYWJhYmFiYWJhYmFiYWJhYmFiYWJhYmFiYWJhYmFiYWIwMGMwYzBjMGMwYzBjMGMwYzBjMGMwYzBjMGMwYzBjMGMwYzBjMGMwYzBjMGMwYzBjAQoAAAAAAAAAA1hVUw==");
+    }
+
+    #[test]
+    fn query_req_data_wire_format() {
+        let json = |request: &QueryReqData| serde_json::to_string(request).unwrap();
+        assert_eq!(json(&QueryReqData::GetSignedTransactions { start: 7 }), r#"{"GetSignedTransactions":{"start":7}}"#);
+        assert_eq!(json(&QueryReqData::CurrentState), r#""CurrentState""#);
+        assert_eq!(json(&QueryReqData::GetEventCursor { event_key: "ab".to_string() }), r#"{"GetEventCursor":{"event_key":"ab"}}"#);
+    }
+
+    #[test]
+    fn request_rpc_retries_only_retryable_errors() {
+        let calls_for = |code: i16| {
+            let mut errors = HashMap::new();
+            errors.insert("get_metadata".to_string(), json!({ "code": code, "message": "error", "data": null }));
+            let server = MockRpcServer::start_with_errors(HashMap::new(), errors);
+            let mut diem = DiemBridgeBuilder::new(&server.url).rpc_retries(2).build().unwrap();
+            assert!(matches!(diem.get_chain_metadata(), Err(Error::FailedToGetResponse)));
+            let calls = server.calls.lock().unwrap().len();
+            calls
+        };
+
+        assert_eq!(calls_for(-32000), 3);
+        assert_eq!(calls_for(-32602), 1);
+    }
+
+    #[test]
+    fn retry_budget_is_shared_across_requests() {
+        let mut errors = HashMap::new();
+        errors.insert("get_metadata".to_string(), json!({ "code": -32000, "message": "error", "data": null }));
+        let server = MockRpcServer::start_with_errors(HashMap::new(), errors);
+        let mut diem = DiemBridgeBuilder::new(&server.url)
+            .clock(Arc::new(MockClock::new(1_000)))
+            .rpc_retries(2)
+            .retry_budget(3, 1.0)
+            .build()
+            .unwrap();
+
+        assert!(diem.get_chain_metadata().is_err());
+        assert!(diem.get_chain_metadata().is_err());
+        assert!(diem.get_chain_metadata().is_err());
+        // 3 first attempts and the 3 retries of the budget, the clock never moved to refill it
+        assert_eq!(server.calls.lock().unwrap().len(), 6);
+    }
+
+    #[test]
+    fn pruned_account_state_falls_back_to_archive() {
+        let mut errors = HashMap::new();
+        errors.insert(
+            "get_account_state_with_proof".to_string(),
+            json!({ "code": -32000, "message": "Version 3 has been pruned", "data": null }),
+        );
+        let pruned = MockRpcServer::start_with_errors(HashMap::new(), errors);
+        let mut results = HashMap::new();
+        results.insert("get_account_state_with_proof".to_string(), json!({ "version": 3 }));
+        let archive = MockRpcServer::start(results);
+
+        let batch = || {
+            let mut batch = JsonRpcBatch::new();
+            batch.add_get_account_state_with_proof_request(AccountAddress::from_hex_literal("0x1").unwrap(), Some(3), Some(10));
+            batch
+        };
+
+        let mut diem = DiemBridge::new(&pruned.url).unwrap();
+        assert!(matches!(diem.request_historical_rpc(batch()), Err(Error::VersionPruned)));
+        // Pruning is permanent, it's not retried
+        assert_eq!(pruned.calls.lock().unwrap().len(), 1);
+
+        // Unless the policy says otherwise, e.g. for a node still catching up on a snapshot
+        struct RetryPruned;
+        impl RetryPolicy for RetryPruned {
+            fn should_retry(&self, failure: &RpcFailure) -> bool {
+                matches!(failure, RpcFailure::Pruned(_))
+            }
+        }
+        let mut diem = DiemBridgeBuilder::new(&pruned.url).retry_policy(Arc::new(RetryPruned)).rpc_retries(1).build().unwrap();
+        assert!(matches!(diem.request_historical_rpc(batch()), Err(Error::VersionPruned)));
+        assert_eq!(pruned.calls.lock().unwrap().len(), 3);
+
+        let mut diem = DiemBridgeBuilder::new(&pruned.url).archive_endpoint(Some(archive.url.clone())).build().unwrap();
+        assert!(diem.request_historical_rpc(batch()).is_ok());
+        assert_eq!(*archive.calls.lock().unwrap(), vec!["get_account_state_with_proof".to_string()]);
+    }
+
+    #[test]
+    fn auth_key_must_end_with_address() {
+        let address = AccountAddress::from_hex_literal("0x1234").unwrap();
+        let mut authentication_key = vec![0xffu8; 16];
+        authentication_key.extend_from_slice(&address.to_vec());
+
+        assert!(check_auth_key(address, Some(&authentication_key)).is_ok());
+        assert!(check_auth_key(address, None).is_ok());
+
+        let other = AccountAddress::from_hex_literal("0x5678").unwrap();
+        assert!(matches!(check_auth_key(other, Some(&authentication_key)), Err(Error::AuthKeyAddressMismatch { .. })));
+        assert!(matches!(check_auth_key(address, Some(&[0u8; 4])), Err(Error::AuthKeyAddressMismatch { .. })));
+    }
+
+    #[test]
+    fn validator_set_of_latest_epoch() {
+        use diem_crypto::HashValue;
+        use diem_types::{block_info::BlockInfo, ledger_info::LedgerInfo, validator_verifier::ValidatorVerifier};
+
+        let mut diem = DiemBridge::new("http://127.0.0.1:8080").unwrap();
+        assert!(diem.validator_set().is_none());
+
+        let epoch_state = EpochState { epoch: 2, verifier: ValidatorVerifier::new(BTreeMap::new()) };
+        diem.latest_epoch_change_li = Some(LedgerInfoWithSignatures::new(
+            LedgerInfo::new(
+                BlockInfo::new(1, 0, HashValue::zero(), HashValue::zero(), 0, 0, Some(epoch_state.clone())),
+                HashValue::zero(),
+            ),
+            BTreeMap::new(),
+        ));
+        assert_eq!(diem.validator_set(), Some(&epoch_state));
+    }
+
+    #[test]
+    fn sync_lag_grows_while_accounts_fail() {
+        let mut diem = DiemBridgeBuilder::new("http://127.0.0.1:8080").lag_ema_alpha(0.5).build().unwrap();
+        let pass = |diem: &mut DiemBridge, tip: u64, processed: &[u64], error: Option<&str>| {
+            diem.summary = SyncSummary::default();
+            diem.summary.verified_version = tip;
+            for version in processed {
+                diem.clear_pending("a", *version);
+            }
+            diem.summary.accounts.entry("a".to_string()).or_default().error = error.map(str::to_string);
+            diem.record_sync_lag();
+            (diem.summary.sync_lag, diem.summary.sync_lag_ema)
+        };
+
+        assert_eq!(pass(&mut diem, 100, &[], None), (0, 0.0));
+        // The account timed out after version 120, the chain moved to 140
+        assert_eq!(pass(&mut diem, 140, &[110, 120], Some("timeout")), (20, 10.0));
+        assert_eq!(pass(&mut diem, 180, &[], Some("timeout")), (60, 35.0));
+        assert_eq!(pass(&mut diem, 190, &[150], None), (0, 17.5));
+
+        assert!(matches!(DiemBridgeBuilder::new("http://127.0.0.1:8080").lag_ema_alpha(0.0).build(), Err(Error::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn pending_versions_until_submitted_or_skipped() {
+        let mut diem = DiemBridge::new("http://127.0.0.1:8080").unwrap();
+        assert!(diem.pending_versions("a").is_empty());
+
+        for version in &[9, 3, 5] {
+            diem.mark_pending("a", *version);
+        }
+        diem.record_submitted("a", 3);
+        diem.record_skip("a", 9, "write set transaction".to_string());
+        assert_eq!(diem.pending_versions("a"), vec![5]);
+        assert!(diem.pending_versions("b").is_empty());
+    }
+
+    #[test]
+    fn waypoint_of_epoch_boundary() {
+        use diem_crypto::HashValue;
+        use diem_types::{block_info::BlockInfo, ledger_info::LedgerInfo, validator_verifier::ValidatorVerifier};
+
+        let epoch_state = EpochState { epoch: 1, verifier: ValidatorVerifier::new(BTreeMap::new()) };
+        let li = LedgerInfo::new(
+            BlockInfo::new(0, 0, HashValue::zero(), HashValue::zero(), 7, 0, Some(epoch_state)),
+            HashValue::zero(),
+        );
+        let proof = EpochChangeProof::new(vec![LedgerInfoWithSignatures::new(li.clone(), BTreeMap::new())], false);
+
+        let waypoint = epoch_boundary_waypoint(&proof, 7).unwrap();
+        assert_eq!(waypoint, Waypoint::new_epoch_boundary(&li).unwrap());
+        assert_eq!(Waypoint::from_str(&waypoint.to_string()).unwrap(), waypoint);
+        assert!(epoch_boundary_waypoint(&proof, 8).is_none());
+    }
+
+    #[test]
+    fn transactions_near_the_tip_are_unconfirmed() {
+        use diem_crypto::HashValue;
+        use diem_types::{block_info::BlockInfo, ledger_info::LedgerInfo, validator_verifier::ValidatorVerifier};
+
+        let epoch_state = EpochState { epoch: 1, verifier: ValidatorVerifier::new(BTreeMap::new()) };
+        let li = LedgerInfo::new(
+            BlockInfo::new(0, 0, HashValue::zero(), HashValue::zero(), 100, 0, Some(epoch_state)),
+            HashValue::zero(),
+        );
+
+        let diem = DiemBridge::new("http://127.0.0.1:8080").unwrap();
+        assert!(diem.is_confirmed(1_000));
+
+        let mut diem = DiemBridgeBuilder::new("http://127.0.0.1:8080").confirmation_versions(10).build().unwrap();
+        assert!(!diem.is_confirmed(1), "nothing is confirmed before the state is verified");
+        diem.trusted_state = Some(TrustedState::try_from(&li).unwrap());
+        assert!(diem.is_confirmed(90));
+        assert!(!diem.is_confirmed(91));
+    }
+
+    #[test]
+    fn latest_li_must_be_in_trusted_epoch() {
+        use diem_crypto::HashValue;
+        use diem_types::{block_info::BlockInfo, ledger_info::LedgerInfo, validator_verifier::ValidatorVerifier};
+
+        let li = |epoch, version, next_epoch: Option<u64>| LedgerInfoWithSignatures::new(
+            LedgerInfo::new(
+                BlockInfo::new(epoch, 0, HashValue::zero(), HashValue::zero(), version, 0,
+                    next_epoch.map(|epoch| EpochState { epoch, verifier: ValidatorVerifier::new(BTreeMap::new()) })),
+                HashValue::zero(),
+            ),
+            BTreeMap::new(),
+        );
+        let epoch_change = li(1, 7, Some(2));
+
+        assert!(check_ledger_state(&li(2, 9, None), Some(&epoch_change)).is_ok());
+        assert!(check_ledger_state(&epoch_change, Some(&epoch_change)).is_ok());
+        assert!(matches!(
+            check_ledger_state(&li(1, 5, None), Some(&epoch_change)),
+            Err(Error::InconsistentLedgerState { li_epoch: 1, trusted_epoch: 2 })
+        ));
+        assert!(matches!(check_ledger_state(&li(2, 9, None), None), Err(Error::StateNotInitialized)));
+    }
+
+    #[test]
+    fn epoch_change_matches_any_waypoint() {
+        use diem_crypto::HashValue;
+        use diem_types::{block_info::BlockInfo, ledger_info::LedgerInfo, validator_verifier::ValidatorVerifier};
+
+        let epoch_state = EpochState { epoch: 1, verifier: ValidatorVerifier::new(BTreeMap::new()) };
+        let li = LedgerInfo::new(
+            BlockInfo::new(0, 0, HashValue::zero(), HashValue::zero(), 7, 0, Some(epoch_state)),
+            HashValue::zero(),
+        );
+        let proof = EpochChangeProof::new(vec![LedgerInfoWithSignatures::new(li.clone(), BTreeMap::new())], false);
+        let expected = Waypoint::new_epoch_boundary(&li).unwrap();
+        let other = Waypoint::from_str(&format!("7:{}", HashValue::zero().to_hex())).unwrap();
+
+        assert_eq!(matching_waypoint(&[other, expected], &proof, 0), Some(&expected));
+        assert!(matching_waypoint(&[other], &proof, 0).is_none());
+        // Epoch changes up to the trusted version were already checked
+        assert!(matching_waypoint(&[expected], &proof, 7).is_none());
+    }
+
+    #[test]
+    fn failed_transactions_skipped_on_demand() {
+        let transaction = |vm_status| TransactionView {
+            version: 3,
+            transaction: TransactionDataView::UnknownTransaction {},
+            hash: String::new(),
+            bytes: BytesView(String::new()),
+            events: vec![],
+            vm_status,
+            gas_used: 9,
+        };
+        let out_of_gas = transaction(VMStatusView::OutOfGas);
+
+        let diem = DiemBridge::new("http://127.0.0.1:8080").unwrap();
+        assert!(diem.failed_on_chain(&out_of_gas).is_none());
+
+        let diem = DiemBridgeBuilder::new("http://127.0.0.1:8080").skip_failed_txns(true).build().unwrap();
+        assert_eq!(diem.failed_on_chain(&out_of_gas), Some("failed on chain: OutOfGas, gas used: 9".to_string()));
+        assert!(diem.failed_on_chain(&transaction(VMStatusView::Executed)).is_none());
+    }
+
+    #[test]
+    fn replays_recorded_rpc_offline() {
+        let path = std::env::temp_dir().join(format!("pdiem-rpc-{}.jsonl", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        let _ = std::fs::remove_file(&path);
+
+        let server = metadata_server(2);
+        let mut diem = DiemBridgeBuilder::new(&server.url).record_rpc(Some(path.clone())).build().unwrap();
+        assert_eq!(diem.get_chain_metadata().unwrap().version, 42);
+        let calls = server.calls.lock().unwrap().len();
+
+        // The response can only come from the recording, the server isn't asked again
+        let mut diem = DiemBridgeBuilder::new(&server.url).replay_rpc(Some(path.clone())).build().unwrap();
+        assert_eq!(diem.get_chain_metadata().unwrap().version, 42);
+        assert_eq!(server.calls.lock().unwrap().len(), calls);
+
+        // Exchanges are kept per endpoint
+        let mut other = DiemBridgeBuilder::new("http://127.0.0.1:1").replay_rpc(Some(path.clone())).build().unwrap();
+        assert!(other.get_chain_metadata().is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn self_transfer_handled_once_per_pass() {
+        let mut diem = DiemBridge::new("http://127.0.0.1:8080").unwrap();
+        // Submitted by the received path, the sent path must not submit it again
+        diem.mark_pending("a", 7);
+        diem.record_submitted("a", 7);
+        assert!(diem.is_handled("a", 7));
+        assert!(!diem.is_handled("b", 7));
+
+        diem.record_skip("a", 8, "write set transaction".to_string());
+        assert!(diem.is_handled("a", 8));
+    }
+
+    #[test]
+    fn epoch_change_proof_extended_with_new_epochs() {
+        use diem_crypto::HashValue;
+        use diem_types::{block_info::BlockInfo, ledger_info::LedgerInfo};
+
+        let li = |epoch: u64| LedgerInfoWithSignatures::new(
+            LedgerInfo::new(BlockInfo::new(epoch, 0, HashValue::zero(), HashValue::zero(), epoch * 10, 0, None), HashValue::zero()),
+            BTreeMap::new(),
+        );
+        let known = EpochChangeProof::new(vec![li(0), li(1)], false);
+        let delta = EpochChangeProof::new(vec![li(1), li(2), li(3)], false);
+
+        let extended = extend_epoch_change_proof(known, delta);
+        let epochs: Vec<u64> = extended.ledger_info_with_sigs.iter().map(|li| li.ledger_info().epoch()).collect();
+        assert_eq!(epochs, vec![0, 1, 2, 3]);
+
+        let mut diem = DiemBridge::new("http://127.0.0.1:8080").unwrap();
+        assert!(matches!(diem.ratchet_to_latest(), Err(Error::StateNotInitialized)));
+    }
+
+    #[test]
+    fn missing_transaction_by_version() {
+        let server = metadata_server(2);
+        let mut diem = DiemBridge::new(&server.url).unwrap();
+
+        assert!(matches!(diem.get_transaction_by_version(7), Err(Error::NoTransaction)));
+    }
+
+    fn empty_proof(version: u64, ledger_version: u64) -> TransactionWithProof {
+        use diem_crypto::HashValue;
+        use diem_types::{block_info::BlockInfo, ledger_info::LedgerInfo, vm_status::KeptVMStatus};
+
+        TransactionWithProof {
+            transaction_bytes: vec![],
+            epoch_change_proof: EpochChangeProof::new(vec![], false),
+            ledger_info_with_signatures: LedgerInfoWithSignatures::new(
+                LedgerInfo::new(
+                    BlockInfo::new(0, 0, HashValue::zero(), HashValue::zero(), ledger_version, 0, None),
+                    HashValue::zero(),
+                ),
+                BTreeMap::new(),
+            ),
+            ledger_info_to_transaction_info_proof: TransactionAccumulatorProof::new(vec![]),
+            transaction_info: TransactionInfo::new(
+                HashValue::zero(), HashValue::zero(), HashValue::zero(), 0, KeptVMStatus::Executed),
+            transaction_info_to_account_proof: SparseMerkleProof::new(None, vec![]),
+            account_state_blob: AccountStateBlob::from(vec![]),
+            version,
+            events: vec![],
+            vm_status: None,
+            gas_used: 0,
+        }
+    }
+
+    #[test]
+    fn verify_transaction_with_proof_requires_trusted_state() {
+        let diem = DiemBridge::new("http://127.0.0.1:8080").unwrap();
+        let proof = empty_proof(0, 0);
+
+        assert!(matches!(diem.verify_transaction_with_proof(AccountAddress::ZERO, &proof), Err(Error::StateNotInitialized)));
+    }
+
+    #[test]
+    fn proofs_grouped_by_ledger_info() {
+        let proofs = vec![empty_proof(1, 10), empty_proof(2, 10), empty_proof(3, 20), empty_proof(4, 10)];
+        let single_size: usize = proofs.iter().map(|p| bcs::to_bytes(p).unwrap().len()).sum();
+
+        let groups = TransactionsWithProof::group(proofs);
+        let versions: Vec<Vec<u64>> = groups.iter().map(|g| g.versions()).collect();
+        assert_eq!(versions, vec![vec![1, 2], vec![3], vec![4]]);
+
+        let grouped_size: usize = groups.iter().map(|g| bcs::to_bytes(g).unwrap().len()).sum();
+        assert!(grouped_size < single_size);
+    }
+
+    #[tokio::test]
+    async fn empty_signed_transaction_queue_keeps_start_seq() {
+        let mut diem = DiemBridge::new("http://127.0.0.1:8080").unwrap();
+        let queue_b64 = base64::encode(&Vec::<TransactionData>::new().encode());
+        let pr = MockPRuntime::new(vec![QueryRespData::GetSignedTransactions { queue_b64 }]);
+
+        let mut start_seq = 5;
+        diem.maybe_submit_signed_transaction(&pr, &mut start_seq).await.unwrap();
+
+        let requests = pr.requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        assert!(matches!(requests[0], (DIEM_CONTRACT_ID, QueryReqData::GetSignedTransactions { start: 5 })));
+        assert_eq!(start_seq, 5);
+    }
+
+    #[tokio::test]
+    async fn malformed_signed_transaction_queue_is_an_error() {
+        let mut diem = DiemBridge::new("http://127.0.0.1:8080").unwrap();
+        let mut start_seq = 5;
+
+        let pr = MockPRuntime::new(vec![QueryRespData::GetSignedTransactions { queue_b64: "not base64!".to_string() }]);
+        let result = diem.maybe_submit_signed_transaction(&pr, &mut start_seq).await;
+        assert!(matches!(result, Err(Error::MalformedProofData { field: "signed_transactions_queue" })));
+
+        let queue_b64 = base64::encode(&[0xff, 0xff, 0xff]);
+        let pr = MockPRuntime::new(vec![QueryRespData::GetSignedTransactions { queue_b64 }]);
+        let result = diem.maybe_submit_signed_transaction(&pr, &mut start_seq).await;
+        assert!(matches!(result, Err(Error::MalformedProofData { field: "signed_transactions_queue" })));
+
+        let queue = vec![TransactionData { sequence: 6, address: vec![0; 16], signed_tx: vec![1, 2, 3], new_account: false }];
+        let pr = MockPRuntime::new(vec![QueryRespData::GetSignedTransactions { queue_b64: base64::encode(&queue.encode()) }]);
+        let result = diem.maybe_submit_signed_transaction(&pr, &mut start_seq).await;
+        assert!(matches!(result, Err(Error::MalformedProofData { field: "signed_tx" })));
+        assert_eq!(start_seq, 5);
+    }
+
+    #[test]
+    fn export_formats_share_the_record() {
+        let transaction = TransactionView {
+            version: 5,
+            transaction: TransactionDataView::UserTransaction {
+                sender: BytesView("aa".to_string()),
+                signature_scheme: String::new(),
+                signature: BytesView(String::new()),
+                public_key: BytesView(String::new()),
+                sequence_number: 0,
+                chain_id: 2,
+                max_gas_amount: 0,
+                gas_unit_price: 0,
+                gas_currency: "XUS".to_string(),
+                expiration_timestamp_secs: 0,
+                script_hash: BytesView(String::new()),
+                script_bytes: BytesView(String::new()),
+                script: ScriptView::PeerToPeer {
+                    receiver: "bb".to_string(),
+                    amount: 100,
+                    currency: "XUS".to_string(),
+                    metadata: BytesView(String::new()),
+                    metadata_signature: BytesView(String::new()),
+                },
+            },
+            hash: String::new(),
+            bytes: BytesView(String::new()),
+            events: vec![],
+            vm_status: VMStatusView::Executed,
+            gas_used: 0,
+        };
+        let proof = empty_proof(5, 6);
+        let record = ExportRecord { account_address: "bb", transaction: &transaction, proof: &proof };
+
+        let csv = record.encode(ExportFormat::Csv).unwrap();
+        assert_eq!(String::from_utf8(csv).unwrap(), "5,bb,aa,peer_to_peer,100,XUS,executed,0,0\n");
+        let mut quoted = transaction.clone();
+        if let TransactionDataView::UserTransaction { script: ScriptView::PeerToPeer { currency, .. }, .. } = &mut quoted.transaction {
+            *currency = "X,\"US".to_string();
+        }
+        let csv = ExportRecord { account_address: "bb", transaction: &quoted, proof: &proof }.encode(ExportFormat::Csv).unwrap();
+        assert_eq!(String::from_utf8(csv).unwrap(), "5,bb,aa,peer_to_peer,100,\"X,\"\"US\",executed,0,0\n");
+
+        let jsonl: serde_json::Value = serde_json::from_slice(&record.encode(ExportFormat::Jsonl).unwrap()).unwrap();
+        assert_eq!(jsonl["amount"], 100);
+        assert_eq!(jsonl["vm_status"]["type"], "executed");
+        assert_eq!(jsonl["events"], json!([]));
+        assert_eq!(jsonl["transaction_with_proof"], hex::encode(bcs::to_bytes(&proof).unwrap()));
+
+        let data = record.encode(ExportFormat::Bcs).unwrap();
+        let len = u32::from_le_bytes([data[0], data[1], data[2], data[3]]) as usize;
+        assert_eq!(data.len(), 4 + len);
+        let (account, decoded): (String, TransactionWithProof) = bcs::from_bytes(&data[4..]).unwrap();
+        assert_eq!(account, "bb");
+        assert_eq!(decoded.version, 5);
+
+        assert!("xml".parse::<ExportFormat>().is_err());
+
+        let path = std::env::temp_dir().join(format!("pdiem-export-{}.csv", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut exporter = Exporter::open(path.to_str().unwrap(), ExportFormat::Csv, 0).unwrap();
+        exporter.write(&record).unwrap();
+        exporter.write(&record).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 2);
+        // Only the latest versions are remembered, memory stays flat over a long session
+        let mut later = transaction.clone();
+        for version in 6..6 + MIN_DEDUP_WINDOW as u64 {
+            later.version = version;
+            exporter.write(&ExportRecord { account_address: "bb", transaction: &later, proof: &proof }).unwrap();
+        }
+        exporter.write(&record).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 3 + MIN_DEDUP_WINDOW);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn account_advanced_mid_pass_is_refetched() {
+        let address = AccountAddress::from_hex_literal("0xd4f0c053205ba934bb2ac0c4e8479e77").unwrap();
+        let mut results = single_transaction_chain();
+        results.insert("get_account".to_string(), json!({
+            "address": "d4f0c053205ba934bb2ac0c4e8479e77",
+            "balances": [],
+            "sequence_number": 5,
+            "authentication_key": "",
+            "sent_events_key": "",
+            "received_events_key": "",
+            "delegated_key_rotation_capability": false,
+            "delegated_withdrawal_capability": false,
+            "is_frozen": false,
+            "role": { "type": "unknown" },
+        }));
+        let server = MockRpcServer::start(results);
+        let mut diem = DiemBridge::new(&server.url).unwrap();
+        diem.refresh_trusted_state().unwrap();
+        let pin = |diem: &mut DiemBridge, sequence_number| {
+            diem.account.insert("d4f0c053205ba934bb2ac0c4e8479e77".to_string(), AccountData {
+                address,
+                authentication_key: None,
+                key_pair: None,
+                sequence_number,
+                status: AccountStatus::Persisted,
+            });
+        };
+
+        let state_proofs = |server: &MockRpcServer| {
+            server.calls.lock().unwrap().iter().filter(|method| *method == "get_state_proof").count()
+        };
+        let initial_state_proofs = state_proofs(&server);
+
+        // The pass pinned sequence 3, the account sent two more transactions meanwhile: they are
+        // only provable once the trusted state is ratcheted again
+        pin(&mut diem, 3);
+        let latest = diem.account_advanced("d4f0c053205ba934bb2ac0c4e8479e77").unwrap();
+        assert!(matches!(latest, Refetch::Advanced(view) if view.sequence_number == 5));
+        assert_eq!(state_proofs(&server), initial_state_proofs + 1);
+
+        pin(&mut diem, 5);
+        assert!(matches!(diem.account_advanced("d4f0c053205ba934bb2ac0c4e8479e77").unwrap(), Refetch::Current));
+        assert_eq!(state_proofs(&server), initial_state_proofs + 1);
+        assert!(matches!(diem.account_advanced("00"), Err(Error::StateNotInitialized)));
+    }
+
+    #[test]
+    fn epoch_change_mid_pass_is_left_to_the_next_pass() {
+        use diem_crypto::HashValue;
+        use diem_types::{block_info::BlockInfo, ledger_info::LedgerInfo};
+
+        let account = "d4f0c053205ba934bb2ac0c4e8479e77";
+        let server = MockRpcServer::start(single_transaction_chain());
+        let mut diem = DiemBridge::new(&server.url).unwrap();
+        diem.refresh_trusted_state().unwrap();
+        pin_account(&mut diem, account);
+        let pass_li = diem.latest_li.clone();
+
+        // The account sent a transaction and the chain moved past the trusted epoch 1 meanwhile;
+        // the ledger info is never verified, the epoch alone keeps it out
+        server.set_result("get_account", json!({
+            "address": account,
+            "balances": [],
+            "sequence_number": 1,
+            "authentication_key": "",
+            "sent_events_key": "",
+            "received_events_key": "",
+            "delegated_key_rotation_capability": false,
+            "delegated_withdrawal_capability": false,
+            "is_frozen": false,
+            "role": { "type": "unknown" },
+        }));
+        let next_epoch = LedgerInfoWithSignatures::new(
+            LedgerInfo::new(BlockInfo::new(2, 0, HashValue::zero(), HashValue::zero(), 9, 0, None), HashValue::zero()),
+            BTreeMap::new(),
+        );
+        server.set_result("get_state_proof", json!({
+            "ledger_info_with_signatures": hex::encode(bcs::to_bytes(&next_epoch).unwrap()),
+            "epoch_change_proof": hex::encode(bcs::to_bytes(&EpochChangeProof::new(vec![], false)).unwrap()),
+            "ledger_consistency_proof": "",
+        }));
+
+        assert!(matches!(diem.account_advanced(account).unwrap(), Refetch::EpochChanged));
+        assert_eq!(diem.latest_li, pass_li);
+    }
+
+    #[tokio::test]
+    async fn run_once_surfaces_setup_errors() {
+        let args = Args::from_iter(vec!["pdiem", "--diem-rpc-endpoint", "not an url"]);
+        assert!(matches!(run_once(args).await, Err(Error::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn missing_account_is_not_an_rpc_failure() {
+        let mut results = HashMap::new();
+        results.insert("get_account".to_string(), serde_json::Value::Null);
+        let server = MockRpcServer::start(results);
+        let address = AccountAddress::from_hex_literal("0x1").unwrap();
+
+        let mut diem = DiemBridge::new(&server.url).unwrap();
+        assert!(diem.get_account_view(address).unwrap().is_none());
+
+        let mut diem = DiemBridgeBuilder::new("http://127.0.0.1:1").rpc_retries(0).build().unwrap();
+        assert!(matches!(diem.get_account_view(address), Err(Error::FailedToGetResponse)));
+    }
+
+    /// Rpc results of a single transaction chain whose genesis ledger info, signed by an empty
+    /// validator set, commits to the state of account 0xd4f0c053205ba934bb2ac0c4e8479e77
+    fn single_transaction_chain() -> HashMap<String, serde_json::Value> {
+        use diem_crypto::HashValue;
+        use diem_types::{
+            account_config::BalanceResource, block_info::BlockInfo, block_metadata::BlockMetadata,
+            event::EventHandle, ledger_info::LedgerInfo, proof::SparseMerkleLeafNode,
+            validator_verifier::ValidatorVerifier, vm_status::KeptVMStatus,
+        };
+
+        let address = AccountAddress::from_hex_literal("0xd4f0c053205ba934bb2ac0c4e8479e77").unwrap();
+        let account_resource = AccountResource::new(
+            0, vec![], None, None,
+            EventHandle::new_from_address(&address, 0), EventHandle::new_from_address(&address, 1),
+        );
+        let blob = AccountStateBlob::try_from((&account_resource, &BalanceResource::new(0))).unwrap();
+        let leaf = SparseMerkleLeafNode::new(address.hash(), blob.hash());
+        let transaction = Transaction::BlockMetadata(BlockMetadata::new(HashValue::zero(), 0, 0, vec![], address));
+        let transaction_info = TransactionInfo::new(
+            transaction.hash(), leaf.hash(), HashValue::zero(), 0, KeptVMStatus::Executed);
+        let epoch_state = EpochState { epoch: 1, verifier: ValidatorVerifier::new(BTreeMap::new()) };
+        let li = LedgerInfoWithSignatures::new(
+            LedgerInfo::new(
+                BlockInfo::new(0, 0, HashValue::zero(), transaction_info.hash(), 0, 0, Some(epoch_state)),
+                HashValue::zero(),
+            ),
+            BTreeMap::new(),
+        );
+        fn hex_bcs<T: Serialize>(value: &T) -> String {
+            hex::encode(bcs::to_bytes(value).unwrap())
+        }
+
+        let mut results = HashMap::new();
+        results.insert("get_state_proof".to_string(), json!({
+            "ledger_info_with_signatures": hex_bcs(&li),
+            "epoch_change_proof": hex_bcs(&EpochChangeProof::new(vec![li.clone()], false)),
+            "ledger_consistency_proof": "",
+        }));
+        results.insert("get_transactions".to_string(), json!([{
+            "version": 0,
+            "transaction": { "type": "blockmetadata", "timestamp_usecs": 0 },
+            "hash": transaction.hash().to_hex(),
+            "bytes": hex_bcs(&transaction),
+            "events": [],
+            "vm_status": { "type": "executed" },
+            "gas_used": 0,
+        }]));
+        results.insert("get_account_state_with_proof".to_string(), json!({
+            "version": 0,
+            "blob": hex_bcs(&blob),
+            "proof": {
+                "ledger_info_to_transaction_info_proof": hex_bcs(&TransactionAccumulatorProof::new(vec![])),
+                "transaction_info": hex_bcs(&transaction_info),
+                "transaction_info_to_account_proof": hex_bcs(&SparseMerkleProof::new(Some(leaf), vec![])),
+            },
+        }));
+        results
+    }
+
+    #[test]
+    fn verified_bundle_round_trips() {
+        let server = MockRpcServer::start(single_transaction_chain());
+        let mut diem = DiemBridge::new(&server.url).unwrap();
+
+        let bundle = diem.build_verified_bundle("d4f0c053205ba934bb2ac0c4e8479e77", 0).unwrap();
+        assert_eq!(bundle.version, 0);
+        let decoded: TransactionWithProof = bcs::from_bytes(&bcs::to_bytes(&bundle).unwrap()).unwrap();
+        let address = AccountAddress::from_hex_literal("0xd4f0c053205ba934bb2ac0c4e8479e77").unwrap();
+        diem.verify_transaction_with_proof(address, &decoded).unwrap();
+    }
+
+    #[test]
+    fn proof_of_another_account_is_rejected() {
+        // The endpoint answers with a valid proof, of 0xd4f0c053205ba934bb2ac0c4e8479e77's state
+        let server = MockRpcServer::start(single_transaction_chain());
+        let mut diem = DiemBridge::new(&server.url).unwrap();
+
+        let result = diem.build_verified_bundle("0000000000000000000000000000000a", 0);
+        assert!(matches!(result, Err(Error::AccountMismatch { .. })));
+    }
+
+    #[test]
+    fn malformed_proof_fields_are_reported() {
+        let fields = [
+            ("get_state_proof", "/epoch_change_proof", "epoch_change_proof"),
+            ("get_state_proof", "/ledger_info_with_signatures", "ledger_info_with_signatures"),
+            ("get_account_state_with_proof", "/proof/ledger_info_to_transaction_info_proof",
+                "ledger_info_to_transaction_info_proof"),
+            ("get_account_state_with_proof", "/proof/transaction_info", "transaction_info"),
+            ("get_account_state_with_proof", "/proof/transaction_info_to_account_proof",
+                "transaction_info_to_account_proof"),
+            ("get_account_state_with_proof", "/blob", "account_state_blob"),
+        ];
+        for (method, pointer, field) in fields.iter() {
+            let mut results = single_transaction_chain();
+            *results.get_mut(*method).unwrap().pointer_mut(pointer).unwrap() = json!("ffffff");
+            let server = MockRpcServer::start(results);
+            let mut diem = DiemBridge::new(&server.url).unwrap();
+
+            let result = diem.build_verified_bundle("d4f0c053205ba934bb2ac0c4e8479e77", 0);
+            assert!(matches!(result, Err(Error::MalformedProofData { field: f }) if f == *field), "{}", field);
+        }
+    }
+
+    #[test]
+    fn stats_count_failed_rpc_attempts() {
+        let mut diem = DiemBridgeBuilder::new("http://127.0.0.1:1").rpc_retries(1).build().unwrap();
+        assert_eq!(diem.stats_snapshot(), BridgeStats::default());
+
+        let address = AccountAddress::from_hex_literal("0x1").unwrap();
+        assert!(diem.get_account_view(address).is_err());
+        let stats = diem.stats_snapshot();
+        assert_eq!(stats.rpc_errors, 2);
+        assert_eq!(stats.last_success_secs, None);
+    }
+
+    #[test]
+    fn snapshot_round_trips_against_waypoint() {
+        use diem_crypto::HashValue;
+        use diem_types::{block_info::BlockInfo, ledger_info::LedgerInfo};
+
+        let server = MockRpcServer::start(single_transaction_chain());
+        let mut diem = DiemBridge::new(&server.url).unwrap();
+        diem.refresh_trusted_state().unwrap();
+        diem.cursors.sent_sequence.insert("d4f0c053205ba934bb2ac0c4e8479e77".to_string(), 3);
+        let data = diem.export_snapshot().unwrap();
+
+        let genesis = diem.latest_epoch_change_li.clone().unwrap();
+        let waypoint = Waypoint::new_epoch_boundary(genesis.ledger_info()).unwrap();
+        let mut standby = DiemBridgeBuilder::new(&server.url).waypoints(vec![waypoint]).build().unwrap();
+        standby.import_snapshot(&data).unwrap();
+        assert_eq!(
+            standby.trusted_state.as_ref().map(|s| s.latest_version()),
+            diem.trusted_state.as_ref().map(|s| s.latest_version()),
+        );
+        assert_eq!(standby.cursors().sent_sequence, diem.cursors().sent_sequence);
+
+        let mut unchecked = DiemBridge::new(&server.url).unwrap();
+        assert!(matches!(unchecked.import_snapshot(&data), Err(Error::InvalidSnapshot(_))));
+        // Same version, another chain
+        let other = Waypoint::new_any(&LedgerInfo::new(
+            BlockInfo::new(0, 1, HashValue::zero(), HashValue::zero(), 0, 0, None),
+            HashValue::zero(),
+        ));
+        let mut mismatched = DiemBridgeBuilder::new(&server.url).waypoints(vec![other]).build().unwrap();
+        assert!(matches!(mismatched.import_snapshot(&data), Err(Error::InvalidSnapshot(_))));
+    }
+
+    #[test]
+    fn trusted_state_survives_restarts() {
+        let path = std::env::temp_dir().join(format!("pdiem-trusted-{}.json", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        let server = MockRpcServer::start(single_transaction_chain());
+        let mut diem = DiemBridgeBuilder::new(&server.url).state_file(Some(path.clone())).build().unwrap();
+        diem.refresh_trusted_state().unwrap();
+        diem.save_trusted_state().unwrap();
+
+        let genesis = diem.latest_epoch_change_li.clone().unwrap();
+        let waypoint = Waypoint::new_epoch_boundary(genesis.ledger_info()).unwrap();
+        let mut restarted = DiemBridgeBuilder::new(&server.url)
+            .state_file(Some(path.clone()))
+            .waypoints(vec![waypoint])
+            .build()
+            .unwrap();
+        restarted.load_trusted_state();
+        assert_eq!(restarted.latest_epoch_change_li, diem.latest_epoch_change_li);
+        assert_eq!(
+            restarted.trusted_state.as_ref().map(|s| s.latest_version()),
+            diem.trusted_state.as_ref().map(|s| s.latest_version()),
+        );
+        // The refresh ratchets from the restored state
+        restarted.refresh_trusted_state().unwrap();
+
+        // A bridge without a waypoint can't check the file and starts from genesis
+        let mut unchecked = DiemBridgeBuilder::new(&server.url).state_file(Some(path.clone())).build().unwrap();
+        unchecked.load_trusted_state();
+        assert!(unchecked.trusted_state.is_none());
+        let _ = std::fs::remove_file(format!("{}.trusted", path));
+    }
+
+    #[test]
+    fn rpc_timeouts_stay_within_the_deadline() {
+        // Accepts connections through the backlog but never answers
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let mut diem = DiemBridgeBuilder::new(&url).rpc_retries(0).build().unwrap();
+        diem.deadline = Deadline::after(Duration::from_millis(300));
+
+        let start = Instant::now();
+        let result = diem.get_account_view(AccountAddress::ZERO);
+        assert!(matches!(result, Err(Error::DeadlineExceeded)));
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn transport_retries_are_only_made_by_the_bridge() {
+        use std::sync::atomic::AtomicUsize;
+
+        // Hangs up on every connection
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let accepted = Arc::new(AtomicUsize::new(0));
+        let counter = accepted.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                counter.fetch_add(1, Ordering::SeqCst);
+                drop(stream);
+            }
+        });
+
+        let mut diem = DiemBridgeBuilder::new(&url).rpc_retries(2).build().unwrap();
+        let start = Instant::now();
+        assert!(diem.get_account_view(AccountAddress::ZERO).is_err());
+        // One http attempt per bridge attempt, each drawn from the retry budget
+        assert_eq!(accepted.load(Ordering::SeqCst), 3);
+        // Backed off at least half of 100ms then 200ms between them
+        assert!(start.elapsed() >= Duration::from_millis(150));
+    }
+
+    /// `single_transaction_chain`, with the transaction at version 0 served as a user transaction
+    ///
+    /// Only the view changes, the bytes and hash still prove against the chain's ledger info.
+    fn user_transaction_chain() -> HashMap<String, serde_json::Value> {
+        let mut results = single_transaction_chain();
+        results.get_mut("get_transactions").unwrap()[0]["transaction"] = json!({
+            "type": "user",
+            "sender": "d4f0c053205ba934bb2ac0c4e8479e77",
+            "signature_scheme": "Scheme::Ed25519",
+            "signature": "",
+            "public_key": "",
+            "sequence_number": 0,
+            "chain_id": 4,
+            "max_gas_amount": 0,
+            "gas_unit_price": 0,
+            "gas_currency": "XUS",
+            "expiration_timestamp_secs": 0,
+            "script_hash": "",
+            "script_bytes": "",
+            "script": {
+                "type": "peer_to_peer_with_metadata",
+                "receiver": "d4f0c053205ba934bb2ac0c4e8479e77",
+                "amount": 1,
+                "currency": "XUS",
+                "metadata": "",
+                "metadata_signature": "",
+            },
+        });
+        results
+    }
+
+    fn received_event(sequence_number: u64, transaction_version: u64) -> serde_json::Value {
+        json!({
+            "key": "0100000000000000d4f0c053205ba934bb2ac0c4e8479e77",
+            "sequence_number": sequence_number,
+            "transaction_version": transaction_version,
+            "data": { "type": "unknown" },
+        })
+    }
+
+    fn pin_account(diem: &mut DiemBridge, account: &str) {
+        diem.account.insert(account.to_string(), AccountData {
+            address: AccountAddress::from_hex_literal(&format!("0x{}", account)).unwrap(),
+            authentication_key: None,
+            key_pair: None,
+            sequence_number: 0,
+            status: AccountStatus::Persisted,
+        });
+    }
+
+    #[tokio::test]
+    async fn failed_received_submission_is_retried() {
+        use crate::contract::MockSink;
+
+        let account = "d4f0c053205ba934bb2ac0c4e8479e77";
+        let key = "0100000000000000d4f0c053205ba934bb2ac0c4e8479e77";
+        let mut results = user_transaction_chain();
+        results.insert("get_events".to_string(), json!([received_event(0, 0)]));
+        let server = MockRpcServer::start(results);
+        let mut diem = DiemBridge::new(&server.url).unwrap();
+        diem.refresh_trusted_state().unwrap();
+        pin_account(&mut diem, account);
+        let pr = MockPRuntime::new(vec![]);
+        let mut sink = MockSink { failures: 1, ..Default::default() };
+
+        // The submission fails: the event is left for the next pass
+        diem.sync_receiving_transactions(&pr, key.to_string(), RECEIVING_EVENTS_LIMIT, account.to_string(), &mut sink)
+            .await.unwrap();
+        assert!(sink.commands.is_empty());
+        assert_eq!(diem.pending_versions(account), vec![0]);
+        assert_eq!(diem.cursors.received_sequence.get(account).cloned().unwrap_or(0), 0);
+        assert_eq!(diem.cursors.received_version.get(account), None);
+
+        // A block metadata transaction at version 1 emitted another event meanwhile
+        let mut transactions = user_transaction_chain()["get_transactions"].clone();
+        let mut metadata = transactions[0].clone();
+        metadata["version"] = json!(1);
+        metadata["transaction"] = json!({ "type": "blockmetadata", "timestamp_usecs": 0 });
+        transactions.as_array_mut().unwrap().push(metadata);
+        server.set_result("get_transactions", transactions);
+        server.set_result("get_events", json!([received_event(0, 0), received_event(1, 1)]));
+
+        diem.sync_receiving_transactions(&pr, key.to_string(), RECEIVING_EVENTS_LIMIT, account.to_string(), &mut sink)
+            .await.unwrap();
+        assert_eq!(sink.commands.len(), 1);
+        assert!(sink.commands[0].contains("VerifyTransaction"));
+        assert!(diem.pending_versions(account).is_empty());
+        assert_eq!(diem.cursors.received_sequence.get(account), Some(&2));
+        assert_eq!(diem.cursors.received_version.get(account), Some(&1));
+    }
+
+    #[test]
+    fn balances_are_read_from_the_proven_state() {
+        let account = "d4f0c053205ba934bb2ac0c4e8479e77";
+        let address = AccountAddress::from_hex_literal(&format!("0x{}", account)).unwrap();
+        let server = MockRpcServer::start(single_transaction_chain());
+        let mut diem = DiemBridgeBuilder::new(&server.url).verify_balances(true).strict(true).build().unwrap();
+        diem.refresh_trusted_state().unwrap();
+
+        diem.check_balances(account, address, &["XUS".to_string()]).unwrap();
+        let calls = server.calls.lock().unwrap().clone();
+        assert!(calls.iter().any(|method| method == "get_account_state_with_proof"));
+        assert!(!calls.iter().any(|method| method == "get_account"));
+
+        // A blob the trusted ledger info doesn't commit to
+        let mut state = single_transaction_chain()["get_account_state_with_proof"].clone();
+        let mut blob = state["blob"].as_str().unwrap().to_string();
+        let last = if blob.ends_with('0') { "1" } else { "0" };
+        blob.replace_range(blob.len() - 1.., last);
+        state["blob"] = json!(blob);
+        server.set_result("get_account_state_with_proof", state);
+        let result = diem.check_balances(account, address, &["XUS".to_string()]);
+        assert!(matches!(result, Err(Error::InvalidStateProof(_))));
+    }
+
+    #[tokio::test]
+    async fn batched_cursor_stays_before_a_failed_group() {
+        use crate::contract::MockSink;
+
+        let account = "d4f0c053205ba934bb2ac0c4e8479e77";
+        let mut results = user_transaction_chain();
+        let transactions = results["get_transactions"].clone();
+        results.insert("get_account_transactions".to_string(), transactions);
+        let server = MockRpcServer::start(results);
+        let mut diem = DiemBridgeBuilder::new(&server.url).batch_proofs(true).build().unwrap();
+        diem.refresh_trusted_state().unwrap();
+        pin_account(&mut diem, account);
+        diem.account.get_mut(account).unwrap().sequence_number = 1;
+        let mut sink = MockSink { failures: 1, ..Default::default() };
+
+        let result = diem.sync_sent_transactions(account.to_string(), &mut sink).await;
+        assert!(matches!(result, Err(Error::FailedToCallPushCommand)));
+        assert_eq!(diem.cursors.sent_sequence.get(account), None);
+
+        diem.sync_sent_transactions(account.to_string(), &mut sink).await.unwrap();
+        assert_eq!(sink.commands.len(), 1);
+        assert!(sink.commands[0].contains("VerifyTransactions"));
+        assert_eq!(diem.cursors.sent_sequence.get(account), Some(&1));
+    }
+
+    #[tokio::test]
+    async fn self_transfer_is_submitted_once() {
+        use crate::contract::MockSink;
+
+        // Version 0 pays the account to itself: it's both sent and received
+        let account = "d4f0c053205ba934bb2ac0c4e8479e77";
+        let key = "0100000000000000d4f0c053205ba934bb2ac0c4e8479e77";
+        let mut results = user_transaction_chain();
+        let transactions = results["get_transactions"].clone();
+        results.insert("get_account_transactions".to_string(), transactions);
+        results.insert("get_events".to_string(), json!([received_event(0, 0)]));
+        let server = MockRpcServer::start(results);
+        let mut diem = DiemBridge::new(&server.url).unwrap();
+        diem.refresh_trusted_state().unwrap();
+        pin_account(&mut diem, account);
+        diem.account.get_mut(account).unwrap().sequence_number = 1;
+        let pr = MockPRuntime::new(vec![]);
+        let mut sink = MockSink::default();
+
+        // In the order of `sync_account_view`
+        diem.sync_receiving_transactions(&pr, key.to_string(), RECEIVING_EVENTS_LIMIT, account.to_string(), &mut sink)
+            .await.unwrap();
+        diem.sync_sent_transactions(account.to_string(), &mut sink).await.unwrap();
+
+        assert_eq!(sink.commands.iter().filter(|command| command.contains("VerifyTransaction")).count(), 1);
+        assert_eq!(diem.cursors.sent_sequence.get(account), Some(&1));
+        assert_eq!(diem.cursors.received_sequence.get(account), Some(&1));
+    }
+
+    #[tokio::test]
+    async fn proofs_with_and_without_inline_events() {
+        use crate::contract::MockSink;
+
+        let account = "d4f0c053205ba934bb2ac0c4e8479e77";
+        for include_events in &[true, false] {
+            let mut results = user_transaction_chain();
+            let mut transactions = results["get_transactions"].clone();
+            // Endpoints leave the events out unless asked for them
+            if *include_events {
+                transactions[0]["events"] = json!([received_event(0, 0)]);
+            }
+            results.insert("get_account_transactions".to_string(), transactions);
+            let server = MockRpcServer::start(results);
+            let path = std::env::temp_dir()
+                .join(format!("pdiem-inline-events-{}-{}.jsonl", include_events, std::process::id()));
+            let _ = std::fs::remove_file(&path);
+            let mut diem = DiemBridgeBuilder::new(&server.url)
+                .include_events(*include_events)
+                .export(Some(path.to_str().unwrap().to_string()), ExportFormat::Jsonl)
+                .build()
+                .unwrap();
+            diem.refresh_trusted_state().unwrap();
+            pin_account(&mut diem, account);
+            diem.account.get_mut(account).unwrap().sequence_number = 1;
+            let mut sink = MockSink::default();
+
+            diem.sync_sent_transactions(account.to_string(), &mut sink).await.unwrap();
+            assert_eq!(sink.commands.iter().filter(|command| command.contains("VerifyTransaction")).count(), 1);
+            assert_eq!(diem.cursors.sent_sequence.get(account), Some(&1));
+
+            let exported = std::fs::read_to_string(&path).unwrap();
+            let record: serde_json::Value = serde_json::from_str(exported.lines().next().unwrap()).unwrap();
+            assert_eq!(record["events"].as_array().unwrap().len(), if *include_events { 1 } else { 0 });
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn skipped_transactions_leave_no_submission_marker() {
+        use crate::contract::MockSink;
+
+        let account = "d4f0c053205ba934bb2ac0c4e8479e77";
+        let mut results = user_transaction_chain();
+        results.get_mut("get_transactions").unwrap()[0]["vm_status"] = json!({ "type": "out_of_gas" });
+        let transactions = results["get_transactions"].clone();
+        results.insert("get_account_transactions".to_string(), transactions);
+        let server = MockRpcServer::start(results);
+        let mut diem = DiemBridgeBuilder::new(&server.url).skip_failed_txns(true).dedup_window(0).build().unwrap();
+        diem.refresh_trusted_state().unwrap();
+        pin_account(&mut diem, account);
+        diem.account.get_mut(account).unwrap().sequence_number = 1;
+        let mut sink = MockSink::default();
+
+        diem.sync_sent_transactions(account.to_string(), &mut sink).await.unwrap();
+        assert!(sink.commands.is_empty());
+        assert!(diem.is_handled(account, 0));
+        assert_eq!(diem.cursors.sent_sequence.get(account), Some(&1));
+        assert!(!diem.cursors.submitted.contains_key(account));
+
+        // A zero window still keeps the latest marker
+        diem.record_submitted(account, 1);
+        assert_eq!(diem.cursors.submitted[account], vec![1]);
+    }
+
+    #[test]
+    fn cursors_are_loaded_with_a_state_file() {
+        let path = std::env::temp_dir().join(format!("pdiem-load-cursors-{}.json", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        let mut cursors = SyncCursors::default();
+        cursors.sent_sequence.insert("a".to_string(), 3);
+        cursors.record_submitted("a", 7, 8);
+        cursors.save(&path).unwrap();
+
+        let diem = DiemBridgeBuilder::new("http://127.0.0.1:8080").state_file(Some(path.clone())).build().unwrap();
+        assert!(!diem.verify_only_new);
+        assert_eq!(diem.cursors.sent_sequence.get("a"), Some(&3));
+        assert_eq!(diem.cursors.submitted["a"], vec![7]);
+
+        let diem = DiemBridge::new("http://127.0.0.1:8080").unwrap();
+        assert!(diem.cursors.sent_sequence.is_empty());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn malformed_account_address_fails_its_account_only() {
+        use crate::contract::MockSink;
+
+        let server = MockRpcServer::start(single_transaction_chain());
+        let mut diem = DiemBridge::new(&server.url).unwrap();
+        diem.refresh_trusted_state().unwrap();
+        let pr = MockPRuntime::new(vec![]);
+        let mut sink = MockSink::default();
+
+        let result = diem.sync_account(&pr, "not an address".to_string(), &mut sink).await;
+        assert!(matches!(result, Err(Error::InvalidConfig(_))));
+        assert!(!server.calls.lock().unwrap().iter().any(|method| method == "get_account"));
+    }
+
+    #[tokio::test]
+    async fn received_events_left_behind_are_reported() {
+        use crate::contract::MockSink;
+
+        let account = "d4f0c053205ba934bb2ac0c4e8479e77";
+        let key = "0100000000000000d4f0c053205ba934bb2ac0c4e8479e77";
+        let mut results = user_transaction_chain();
+        results.insert("get_events".to_string(), json!([received_event(0, 0)]));
+        let server = MockRpcServer::start(results);
+        let mut diem = DiemBridge::new(&server.url).unwrap();
+        diem.refresh_trusted_state().unwrap();
+        pin_account(&mut diem, account);
+        let pr = MockPRuntime::new(vec![]);
+        let mut sink = MockSink { failures: 1, ..Default::default() };
+
+        // A failed submission leaves the event to the next pass
+        let caught_up = diem.sync_receiving_transactions(&pr, key.to_string(), RECEIVING_EVENTS_LIMIT, account.to_string(), &mut sink)
+            .await.unwrap();
+        assert!(!caught_up);
+
+        // A full page may be followed by more events, the balance would lag behind them
+        let caught_up = diem.sync_receiving_transactions(&pr, key.to_string(), 1, account.to_string(), &mut sink)
+            .await.unwrap();
+        assert!(!caught_up);
+        assert_eq!(diem.cursors.received_sequence.get(account), Some(&1));
+
+        server.set_result("get_events", json!([]));
+        let caught_up = diem.sync_receiving_transactions(&pr, key.to_string(), RECEIVING_EVENTS_LIMIT, account.to_string(), &mut sink)
+            .await.unwrap();
+        assert!(caught_up);
+    }
+
+    #[tokio::test]
+    async fn failed_received_transaction_fetch_fails_the_account() {
+        use crate::contract::MockSink;
+
+        let account = "d4f0c053205ba934bb2ac0c4e8479e77";
+        let key = "0100000000000000d4f0c053205ba934bb2ac0c4e8479e77";
+        let mut results = user_transaction_chain();
+        results.insert("get_events".to_string(), json!([received_event(0, 0)]));
+        let server = MockRpcServer::start(results);
+        let mut diem = DiemBridgeBuilder::new(&server.url).rpc_retries(0).build().unwrap();
+        diem.refresh_trusted_state().unwrap();
+        pin_account(&mut diem, account);
+        let pr = MockPRuntime::new(vec![]);
+        let mut sink = MockSink::default();
+
+        // The node lost the transaction the event points at
+        server.set_result("get_transactions", json!([]));
+        let result = diem.sync_receiving_transactions(&pr, key.to_string(), RECEIVING_EVENTS_LIMIT, account.to_string(), &mut sink)
+            .await;
+        assert!(matches!(result, Err(Error::NoTransaction)));
+        assert!(sink.commands.is_empty());
+        assert_eq!(diem.cursors.received_sequence.get(account).cloned().unwrap_or(0), 0);
+        assert_eq!(diem.pending_versions(account), vec![0]);
+    }
+}
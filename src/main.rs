@@ -1,641 +1,12 @@
 use structopt::StructOpt;
-use std::collections::BTreeMap;
-
-use diem_client::{
-    AccountData,
-    AccountStatus,
-};
-use anyhow::{ensure, Result};
-use reqwest::Url;
-use diem_crypto::hash::CryptoHash;
-
-use diem_types::{
-    account_address::{
-        AccountAddress, HashAccountAddress
-    },
-    chain_id::{ChainId, NamedChain},
-    ledger_info::LedgerInfoWithSignatures,
-    transaction::{TransactionInfo, SignedTransaction},
-    epoch_change::EpochChangeProof,
-    proof::{
-        AccountStateProof,
-        TransactionInfoWithProof,
-        TransactionAccumulatorProof,
-    },
-    trusted_state::{TrustedState, TrustedStateChange},
-};
-use diem_json_rpc_client::{
-    get_response_from_batch,
-    views::{
-        AccountStateWithProofView, AccountView, BytesView,
-        EventView, StateProofView, TransactionView, TransactionDataView
-    },
-    JsonRpcBatch, JsonRpcClient, ResponseAsView, JsonRpcResponse,
-};
-use std::{convert::TryFrom};
-use diem_types::account_state_blob::AccountStateBlob;
-type SparseMerkleProof = diem_types::proof::SparseMerkleProof<AccountStateBlob>;
-
-mod pruntime_client;
-mod types;
-mod error;
-mod runtimes;
-
-use std::cmp;
-use crate::types::{Runtime, Payload, QueryReqData, QueryRespData, TransactionData};
-use subxt::Signer;
-use subxt::system::AccountStoreExt;
-use core::marker::PhantomData;
-use sp_core::{sr25519, crypto::Pair};
-type SrSigner = subxt::PairSigner<Runtime, sr25519::Pair>;
-type XtClient = subxt::Client<Runtime>;
-
-type PrClient = pruntime_client::PRuntimeClient;
-
-const DIEM_CONTRACT_ID: u32 = 5;
-const RECEIVING_EVENTS_LIMIT: u64 = 100;
-
-use crate::error::Error;
-use crate::types::{CommandReqData};
-
-use serde::{Serialize, Deserialize};
-use codec::Decode;
-
-#[derive(Debug, StructOpt)]
-#[structopt(name = "pDiem")]
-struct Args {
-    #[structopt(
-    default_value = "http://127.0.0.1:8080", long,
-    help = "Diem rpc endpoint")]
-    diem_rpc_endpoint: String, //official rpc endpoint: https://testnet.diem.com
-
-    #[structopt(
-    default_value = "http://127.0.0.1:8000", long,
-    help = "pRuntime http endpoint")]
-    pruntime_endpoint: String,
-
-    #[structopt(
-    required = true,
-    default_value = "//Alice",
-    short = "m", long = "mnemonic",
-    help = "Controller SR25519 private key mnemonic, private key seed, or derive path")]
-    mnemonic: String,
-
-    #[structopt(
-    default_value = "ws://localhost:9944", long,
-    help = "Substrate rpc websocket endpoint")]
-    substrate_ws_endpoint: String,
-
-    #[structopt(default_value = "15", long,
-    help = "The interval in seconds.")]
-    interval: u64,
-}
-
-pub struct DiemBridge {
-    chain_id: ChainId,
-    rpc_client: JsonRpcClient,
-    epoch_change_proof: Option<EpochChangeProof>,
-    trusted_state: Option<TrustedState>,
-    latest_epoch_change_li: Option<LedgerInfoWithSignatures>,
-    latest_li: Option<LedgerInfoWithSignatures>,
-    received_events: BTreeMap<String, Vec<EventView>>,
-    transactions: BTreeMap<String, Vec<TransactionView>>,
-    account: BTreeMap<String, AccountData>,
-    address: Vec<String>,
-}
-
-#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
-pub struct Amount {
-    pub amount: u64,
-    pub currency: String,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct AccountInfo {
-    pub address: AccountAddress,
-    pub authentication_key: Option<Vec<u8>>,
-    pub sequence_number: u64,
-    pub sent_events_key: String,
-    pub received_events_key: String,
-    pub balances: Vec<Amount>,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct TransactionWithProof {
-    transaction_bytes: Vec<u8>,
-
-    epoch_change_proof: EpochChangeProof,
-    ledger_info_with_signatures: LedgerInfoWithSignatures,
-
-    ledger_info_to_transaction_info_proof: TransactionAccumulatorProof,
-    transaction_info: TransactionInfo,
-    transaction_info_to_account_proof: SparseMerkleProof,
-    account_state_blob: AccountStateBlob,
-
-    version: u64,
-}
-
-impl DiemBridge {
-    pub fn new(url: &str) -> Result<Self> {
-        let rpc_client = JsonRpcClient::new(Url::parse(url).unwrap()).unwrap();
-        let chain_id = if url == "https://testnet.diem.com" {
-            NamedChain::TESTNET
-        } else {
-            NamedChain::TESTING
-        };
-        println!("{}", url);
-        Ok(DiemBridge {
-            chain_id: ChainId::new(chain_id.id()),
-            rpc_client,
-            epoch_change_proof: None,
-            trusted_state: None,
-            latest_epoch_change_li: None,
-            latest_li: None,
-            received_events: BTreeMap::<String, Vec<EventView>>::new(),
-            transactions: BTreeMap::<String, Vec<TransactionView>>::new(),
-            account: BTreeMap::<String, AccountData>::new(),
-            address: Vec::new(),
-        })
-    }
-
-    fn verify_state_proof(
-        &mut self,
-        li: LedgerInfoWithSignatures,
-        epoch_change_proof: EpochChangeProof
-    ) -> Result<()> {
-        let client_version = self.trusted_state.as_mut().unwrap().latest_version();
-        // check ledger info version
-        ensure!(
-            li.ledger_info().version() >= client_version,
-            "Got stale ledger_info with version {}, known version: {}",
-            li.ledger_info().version(),
-            client_version,
-        );
-
-        // trusted_state_change
-        match self.trusted_state.as_mut().unwrap().verify_and_ratchet(&li, &epoch_change_proof)?
-        {
-            TrustedStateChange::Epoch {
-                new_state,
-                latest_epoch_change_li,
-            } => {
-                println!(
-                    "Verified epoch changed to {}",
-                    latest_epoch_change_li
-                        .ledger_info()
-                        .next_epoch_state()
-                        .expect("no validator set in epoch change ledger info"),
-                );
-                // Update client state
-                self.trusted_state = Some(new_state);
-                self.latest_epoch_change_li = Some(latest_epoch_change_li.clone());
-            }
-            TrustedStateChange::Version { new_state } => {
-                if self.trusted_state.as_mut().unwrap().latest_version() < new_state.latest_version() {
-                    println!("Verified version change to: {}", new_state.latest_version());
-                }
-                self.trusted_state = Some(new_state);
-            }
-            TrustedStateChange::NoChange => (),
-        }
-        Ok(())
-    }
-
-    async fn init_state(
-        &mut self,
-        pr: Option<&PrClient>,
-        client: &XtClient,
-        signer: &mut SrSigner,
-        initialized: bool,
-    ) -> Result<(), Error> {
-        let mut batch = JsonRpcBatch::new();
-        batch.add_get_state_proof_request(0);
-        if let Ok(resp) = self.request_rpc(batch) {
-            let state_proof = StateProofView::from_response(resp).unwrap();
-
-            let epoch_change_proof: EpochChangeProof =
-                bcs::from_bytes(&state_proof.epoch_change_proof.into_bytes().unwrap()).unwrap();
-            let ledger_info_with_signatures: LedgerInfoWithSignatures =
-                bcs::from_bytes(&state_proof.ledger_info_with_signatures.into_bytes().unwrap()).unwrap();
-
-            // Init zero version state
-            let zero_ledger_info_with_sigs = epoch_change_proof.ledger_info_with_sigs[0].clone();
-
-            self.latest_epoch_change_li = Some(zero_ledger_info_with_sigs.clone());
-            self.trusted_state = Some(TrustedState::try_from(zero_ledger_info_with_sigs.ledger_info()).unwrap());
-            self.latest_li = Some(ledger_info_with_signatures.clone());
-            self.epoch_change_proof = Some(epoch_change_proof.clone());
-
-            // Update Latest version state
-            let _ = self.verify_state_proof(ledger_info_with_signatures.clone(), epoch_change_proof.clone());
-            println!("trusted_state: {:#?}", self.trusted_state);
-            println!("ledger_info_with_signatures: {:#?}", self.latest_li);
-
-            if pr.is_some() {
-                if initialized {
-                    let trusted_state_b64 = base64::encode(&bcs::to_bytes(&zero_ledger_info_with_sigs).unwrap());
-
-                    let command_value = serde_json::to_value(&CommandReqData::SetTrustedState { trusted_state_b64, chain_id: self.chain_id.id() })?;
-                    let _ = self.push_command(command_value.to_string(), &client, signer).await;
-                } else {
-                    let ledger_info_with_signatures_b64 = base64::encode(&bcs::to_bytes(&ledger_info_with_signatures).unwrap());
-                    let epoch_change_proof_b64 = base64::encode(&bcs::to_bytes(&epoch_change_proof).unwrap());
-
-                    let command_value = serde_json::to_value(&CommandReqData::VerifyEpochProof { ledger_info_with_signatures_b64, epoch_change_proof_b64 })?;
-                    let _ = self.push_command(command_value.to_string(), &client, signer).await;
-                }
-            }
-
-            Ok(())
-        } else {
-            println!("Failed to get init_state");
-            Err(Error::FailedToInitState)
-        }
-    }
-
-    async fn sync_account(
-        &mut self,
-        account_address: String,
-        client: &XtClient,
-        signer: &mut SrSigner,
-    ) -> Result<(), Error> {
-        // Init account information
-        let mut batch = JsonRpcBatch::new();
-        let address = AccountAddress::from_hex_literal(&("0x".to_string() + &account_address)).unwrap();
-        batch.add_get_account_request(address);
-        let resp = self.request_rpc(batch).map_err(|_| Error::FailedToGetResponse)?;
-
-        if let Some(account_view) = AccountView::optional_from_response(resp).unwrap() {
-            self.account.insert(account_address.clone(), AccountData {
-                address,
-                authentication_key: account_view.authentication_key.into_bytes().ok(),
-                key_pair: None,
-                sequence_number: account_view.sequence_number,
-                status: AccountStatus::Persisted,
-            });
-
-            let sent_events_key = account_view.sent_events_key.clone();
-            let received_events_key = account_view.received_events_key.clone();
-            let balances = Some(account_view.balances.clone());
-
-            let amounts: Vec<Amount> = balances.as_ref().unwrap()
-                .iter()
-                .map(|b| Amount{ amount: b.amount, currency: b.currency.clone() }).collect();
-            let account = self.account.get(&account_address).unwrap();
-            let account_info = AccountInfo {
-                address: account.address,
-                authentication_key: account.authentication_key.clone(),
-                sequence_number: account.sequence_number,
-                sent_events_key: sent_events_key.0,
-                received_events_key: received_events_key.0,
-                balances: amounts,
-            };
-
-            let account_info_b64 = base64::encode(&bcs::to_bytes(&account_info).unwrap());
-            let command_value = serde_json::to_value(&CommandReqData::AccountInfo { account_info_b64 })?;
-            let _ = self.push_command(command_value.to_string(), &client, signer).await;
-
-            // Sync receiving transactions
-            let _ = self.sync_receiving_transactions(
-                account_view.received_events_key.0.clone().to_string(),
-                RECEIVING_EVENTS_LIMIT,
-                account_address.clone(),
-                &client,
-                signer,
-            ).await?;
-
-            // Sync sending transactions
-            let _ = self.sync_sent_transactions(account_address, &client, signer).await?;
-        } else {
-            println!("get account view error");
-        }
-
-        Ok(())
-    }
-
-    async fn sync_receiving_transactions(
-        &mut self,
-        received_events_key: String,
-        limit: u64,
-        account_address: String,
-        client: &XtClient,
-        signer: &mut SrSigner,
-    ) -> Result<(), Error> {
-        let mut batch = JsonRpcBatch::new();
-        batch.add_get_events_request(received_events_key.to_string(), 0, limit);
-        let resp = self.request_rpc(batch).map_err(|_| Error::FailedToGetReceivingTransactions)?;
-
-        let received_events = EventView::vec_from_response(resp).unwrap();
-        let mut new_events: Vec<EventView> = Vec::new();
-        for event in received_events.clone() {
-            let exist = self.received_events.get(&account_address).is_some()
-                && self.received_events.get(&account_address).unwrap().iter().any(|x| x.transaction_version == event.transaction_version);
-            if !exist {
-                println!("new received event!");
-                new_events.push(event);
-            }
-        }
-
-        for event in new_events {
-            if let Ok(transaction) = self.get_transaction_by_version(event.transaction_version) {
-                println!("received transaction:{:?}", transaction);
-                let _ = self.sync_transaction_with_proof(
-                    &transaction, account_address.clone(), &client, signer
-                ).await?;
-            } else {
-                println!("get_transaction_by_version error");
-            }
-        }
-
-        self.received_events.insert(account_address, received_events);
-
-        Ok(())
-    }
-
-    async fn sync_sent_transactions(
-        &mut self,
-        account_address: String,
-        client: &XtClient,
-        signer: &mut SrSigner,
-    ) -> Result<(), Error> {
-        println!("account:{:?}", self.account);
-        let mut batch = JsonRpcBatch::new();
-        batch.add_get_account_transactions_request(
-            self.account.get(&account_address).unwrap().address.clone(),
-            0,
-            self.account.get(&account_address).unwrap().sequence_number.clone(),
-            true
-        );
-        let resp = self.request_rpc(batch).map_err(|_| Error::FailedToGetSentTransactions)?;
-        println!("add_get_account_transactions_request resp:{:?}", resp);
-        let mut need_sync_transactions: Vec<TransactionView> = Vec::new();
-        let transactions = TransactionView::vec_from_response(resp).unwrap();
-        for transaction in transactions.clone() {
-            let exist = self.transactions.get(&account_address).is_some()
-                && self.transactions.get(&account_address).unwrap().iter().any(|x| x.version == transaction.version);
-            if !exist {
-                println!("new transaction!");
-                match transaction.transaction {
-                    TransactionDataView::UserTransaction {..} => {
-                        need_sync_transactions.push(transaction);
-                    },
-                    _ => (),
-                }
-            }
-        }
-
-        for transaction in need_sync_transactions {
-            let _ = self.sync_transaction_with_proof(
-                &transaction, account_address.clone(), &client, signer
-            ).await?;
-        }
-
-        self.transactions.insert(account_address, transactions);
-
-        Ok(())
-    }
-
-    async fn sync_transaction_with_proof(
-        &mut self,
-        transaction: &TransactionView,
-        account_address: String,
-        client: &XtClient,
-        signer: &mut SrSigner,
-    ) -> Result<(), Error> {
-        if let Ok(transaction_with_proof) = self.get_transaction_proof(account_address.clone(), &transaction) {
-            println!("transaction_with_proof:{:?}", transaction_with_proof);
-
-            let transaction_with_proof_b64 = base64::encode(&bcs::to_bytes(&transaction_with_proof).unwrap());
-            let command_value = serde_json::to_value(&CommandReqData::VerifyTransaction { account_address, transaction_with_proof_b64 })?;
-            let _ = self.push_command(command_value.to_string(), &client, signer).await;
-        } else {
-            println!("get_transaction_proof error");
-        }
-
-        Ok(())
-    }
-
-    async fn push_command(
-        &mut self,
-        payload: String,
-        client: &XtClient,
-        signer: &mut SrSigner,
-    ) -> Result<(), Error> {
-        let command_payload = serde_json::to_string(&Payload::Plain(payload))?;
-        println!("command_payload:{}", command_payload);
-        let call = runtimes::phala::PushCommandCall {
-            _runtime: PhantomData,
-            contract_id: DIEM_CONTRACT_ID,
-            payload: command_payload.as_bytes().to_vec(),
-        };
-
-        self.update_signer_nonce(client, signer).await?;
-        let ret = client.submit(call, signer).await;
-        if !ret.is_ok() {
-            println!("FailedToCallPushCommand: {:?}", ret);
-            return Err(Error::FailedToCallPushCommand);
-        }
-        signer.increment_nonce();
-
-        Ok(())
-    }
-
-    async fn update_signer_nonce(&self, client: &XtClient, signer: &mut SrSigner) -> Result<(), Error> {
-        let account_id = signer.account_id();
-        let nonce = client.account(account_id, None).await?.nonce;
-        let local_nonce = signer.nonce();
-        signer.set_nonce(cmp::max(nonce, local_nonce.unwrap_or(0)));
-        Ok(())
-    }
-
-    fn get_transaction_proof(
-        &mut self,
-        account_address: String,
-        transaction: &TransactionView,
-    ) -> Result<TransactionWithProof, Error> {
-        let mut batch = JsonRpcBatch::new();
-        let account = self.account.get(&account_address).unwrap().address.clone();
-        batch.add_get_account_state_with_proof_request(
-            account,
-            Some(transaction.version),
-            Some(self.trusted_state.as_ref().unwrap().latest_version()));
-        if let Ok(resp) = self.request_rpc(batch) {
-            let account_state_proof =
-                AccountStateWithProofView::from_response(resp.clone()).unwrap();
-
-            let ledger_info_to_transaction_info_proof: TransactionAccumulatorProof =
-                bcs::from_bytes(&account_state_proof.proof.ledger_info_to_transaction_info_proof.into_bytes().unwrap()).unwrap();
-            let transaction_info: TransactionInfo =
-                bcs::from_bytes(&account_state_proof.proof.transaction_info.into_bytes().unwrap()).unwrap();
-            let transaction_info_to_account_proof: SparseMerkleProof =
-                bcs::from_bytes(&account_state_proof.proof.transaction_info_to_account_proof.into_bytes().unwrap()).unwrap();
-            let account_state_blob: AccountStateBlob =
-                bcs::from_bytes(&account_state_proof.blob.unwrap().into_bytes().unwrap()).unwrap();
-            if transaction_info.transaction_hash().to_hex() != transaction.hash {
-                println!("Bad transaction hash");
-                return Err(Error::BadTransactionHash);
-            }
-            let transaction_info_with_proof = TransactionInfoWithProof::new(
-                ledger_info_to_transaction_info_proof.clone(),
-                transaction_info.clone()
-            );
-
-            let account_transaction_state_proof = AccountStateProof::new(
-                transaction_info_with_proof.clone(),
-                transaction_info_to_account_proof.clone(),
-            );
-            let _ = account_transaction_state_proof.verify(
-                self.latest_li.as_ref().unwrap().ledger_info(),
-                transaction.version,
-                self.account.get(&account_address).unwrap().address.hash(),
-                Some(&account_state_blob),
-            );
-            println!("Transaction was verified");
-
-            let state_proof = TransactionWithProof {
-                transaction_bytes: transaction.bytes.clone().into_bytes().unwrap(),
-                epoch_change_proof: self.epoch_change_proof.clone().unwrap(),
-                ledger_info_with_signatures: self.latest_li.clone().unwrap(),
-                ledger_info_to_transaction_info_proof,
-                transaction_info,
-                transaction_info_to_account_proof,
-                account_state_blob,
-                version: transaction.version,
-            };
-
-            Ok(state_proof)
-        } else {
-            println!("Failed to get account's state with proof");
-            Err(Error::FailedToGetResponse)
-        }
-    }
-
-    fn get_transaction_by_version(
-        &mut self,
-        version: u64
-    ) -> Result<TransactionView, Error> {
-        let mut batch = JsonRpcBatch::new();
-        batch.add_get_transactions_request(version, 1, false);
-        if let Ok(resp) = self.request_rpc(batch) {
-            let transactions = TransactionView::vec_from_response(resp.clone()).unwrap();
-            if transactions.len() == 0 {
-                return Err(Error::NoTransaction);
-            }
-            Ok(transactions[0].clone())
-        } else {
-            Err(Error::FailedToGetTransaction)
-        }
-    }
-
-    fn request_rpc(
-        &mut self,
-        batch: JsonRpcBatch
-    ) -> Result<JsonRpcResponse, Error> {
-        let responses: Vec<Result<JsonRpcResponse>> = self.rpc_client.execute(batch).unwrap_or(Vec::new());
-        println!("rpc responses：{:?}\n", responses);
-        if let Ok(resp) = get_response_from_batch(0, &responses) {
-            if resp.is_ok() {
-                Ok(resp.as_ref().unwrap().clone())
-            } else {
-                Err(Error::FailedToGetResponse)
-            }
-        } else {
-            Err(Error::FailedToGetResponse)
-        }
-    }
-
-    async fn maybe_submit_signed_transaction(
-        &mut self,
-        pr: &PrClient,
-        start_seq: &mut u64,
-    ) -> Result<(), Error> {
-        let resp = pr.query(DIEM_CONTRACT_ID, QueryReqData::GetSignedTransactions { start: *start_seq}).await?;
-        println!("query signed transaction resp:{:?}", resp);
-        if let QueryRespData::GetSignedTransactions { queue_b64 } = resp {
-            let data = base64::decode(&queue_b64).unwrap();
-            let transaction_data: Vec<TransactionData> = Decode::decode(&mut &data[..]).unwrap();
-            for td in &transaction_data {
-                println!("transaction data:{:?}", td);
-                let signed_tx: SignedTransaction = bcs::from_bytes(&td.signed_tx).unwrap();
-                println!("signed transaction:{:?}", signed_tx);
-                let mut batch = JsonRpcBatch::new();
-                let _ = batch.add_submit_request(signed_tx);
-                match self.request_rpc(batch) {
-                    Ok(_) => {
-                        let receiver_address = hex::encode_upper(td.address.clone());
-                        println!("submit transaction for {:?}", receiver_address);
-
-                        if td.new_account && !self.address.contains(&receiver_address) {
-                            self.address.push(receiver_address);
-                        }
-
-                        if td.sequence > *start_seq {
-                            *start_seq = td.sequence
-                        }
-                    }
-                    Err(_) => {
-                        println!("request rpc error");
-                    }
-                }
-
-            }
-            if transaction_data.len() > 0 {
-                *start_seq = *start_seq + 1;
-            }
-        }
-
-        Ok(())
-    }
-}
-
-async fn bridge(args: Args) -> Result<(), Error> {
-    let mut diem = DiemBridge::new(&args.diem_rpc_endpoint).unwrap();
-    let client = subxt::ClientBuilder::<Runtime>::new()
-        .skip_type_sizes_check()
-        .set_url(args.substrate_ws_endpoint.clone())
-        .build().await?;
-    println!("Connected to substrate at: {}", args.substrate_ws_endpoint.clone());
-
-    let pair = <sr25519::Pair as Pair>::from_string(&args.mnemonic, None)
-        .expect("Bad privkey derive path");
-    let mut signer: SrSigner = subxt::PairSigner::new(pair);
-
-    let pr = PrClient::new(&args.pruntime_endpoint);
-    let resp = pr.query(DIEM_CONTRACT_ID, QueryReqData::CurrentState).await?;
-    if let QueryRespData::CurrentState { state } = resp {
-        println!("current state: {:?}", state);
-
-        diem.init_state(Some(&pr), &client, &mut signer, true).await?;
-
-        diem.address = state.account_address;
-        let mut start_seq = state.queue_seq;
-
-        loop {
-            diem.init_state(Some(&pr), &client, &mut signer, false).await?;
-
-            let address = diem.address.clone();
-            for addr in address {
-                println!("sync account: {:}", addr);
-                let _ = diem.sync_account(addr.clone(), &client, &mut signer).await;
-            }
-
-            let _ = diem.maybe_submit_signed_transaction(&pr, &mut start_seq).await;
-
-            println!("Waiting for next loop\n");
-            tokio::time::delay_for(std::time::Duration::from_millis(args.interval * 1000)).await;
-        }
-    } else {
-        println!("query state error");
-    }
-
-    Ok(())
-}
 
 #[tokio::main]
 async fn main() {
-    let args = Args::from_args();
-    match bridge(args).await {
-        Ok(()) => println!("bridge() exited sucessfully"),
-        Err(e) => panic!("bridge() exited with result: {:?}", e)
+    match pdiem::run(pdiem::Args::from_args()).await {
+        Ok(()) => println!("pdiem exited sucessfully"),
+        Err(e) => {
+            println!("pdiem exited with result: {:?}", e);
+            std::process::exit(1);
+        }
     }
 }
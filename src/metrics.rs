@@ -0,0 +1,74 @@
+use std::time::Duration;
+
+/// Upper bounds in milliseconds of the latency buckets, the last bucket is unbounded
+pub const LATENCY_BUCKETS_MS: [u64; 6] = [1, 5, 10, 50, 100, 500];
+
+/// Fixed bucket latency histogram, cheap enough to record on every verified transaction
+#[derive(Debug, Clone, Default)]
+pub struct LatencyHistogram {
+    /// Observation count per bucket, one more than `LATENCY_BUCKETS_MS` for the overflow bucket
+    pub buckets: [u64; LATENCY_BUCKETS_MS.len() + 1],
+    pub count: u64,
+    pub sum_ms: u64,
+}
+
+impl LatencyHistogram {
+    pub fn observe(&mut self, elapsed: Duration) {
+        let ms = elapsed.as_millis() as u64;
+        let bucket = LATENCY_BUCKETS_MS.iter().position(|bound| ms <= *bound)
+            .unwrap_or(LATENCY_BUCKETS_MS.len());
+        self.buckets[bucket] += 1;
+        self.count += 1;
+        self.sum_ms += ms;
+    }
+}
+
+pub const DEFAULT_LAG_EMA_ALPHA: f64 = 0.2;
+
+/// Exponential moving average, smoothing out spikes so only sustained changes move it much
+#[derive(Debug, Clone)]
+pub struct Ema {
+    /// Weight of the newest sample, in `(0, 1]`
+    alpha: f64,
+    value: Option<f64>,
+}
+
+impl Ema {
+    pub fn new(alpha: f64) -> Self {
+        Ema { alpha, value: None }
+    }
+
+    /// Folds in `sample`, the first sample is taken as is
+    pub fn update(&mut self, sample: f64) -> f64 {
+        let value = match self.value {
+            Some(value) => self.alpha * sample + (1.0 - self.alpha) * value,
+            None => sample,
+        };
+        self.value = Some(value);
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn observes_into_buckets() {
+        let mut histogram = LatencyHistogram::default();
+        histogram.observe(Duration::from_micros(300));
+        histogram.observe(Duration::from_millis(7));
+        histogram.observe(Duration::from_secs(2));
+        assert_eq!(histogram.buckets, [1, 0, 1, 0, 0, 0, 1]);
+        assert_eq!(histogram.count, 3);
+        assert_eq!(histogram.sum_ms, 2007);
+    }
+
+    #[test]
+    fn ema_smooths_spikes() {
+        let mut ema = Ema::new(0.5);
+        assert_eq!(ema.update(10.0), 10.0);
+        assert_eq!(ema.update(30.0), 20.0);
+        assert_eq!(ema.update(20.0), 20.0);
+    }
+}
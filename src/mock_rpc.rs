@@ -0,0 +1,95 @@
+//! A minimal Diem JSON-RPC server answering every call of a batch from canned results
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use serde_json::{json, Value};
+
+pub struct MockRpcServer {
+    pub url: String,
+    /// Methods of every call received, in order
+    pub calls: Arc<Mutex<Vec<String>>>,
+    results: Arc<Mutex<HashMap<String, Value>>>,
+}
+
+impl MockRpcServer {
+    /// Serves `results` (method name -> json result) on a random local port
+    pub fn start(results: HashMap<String, Value>) -> Self {
+        Self::start_with_errors(results, HashMap::new())
+    }
+
+    /// Like `start`, but answers the methods in `errors` with the given json-rpc error object
+    pub fn start_with_errors(results: HashMap<String, Value>, errors: HashMap<String, Value>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let results = Arc::new(Mutex::new(results));
+
+        let server_calls = calls.clone();
+        let server_results = results.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                if let Ok(stream) = stream {
+                    handle(stream, &server_results.lock().unwrap(), &errors, &server_calls);
+                }
+            }
+        });
+
+        MockRpcServer { url, calls, results }
+    }
+
+    /// Changes the result served for `method` from now on
+    pub fn set_result(&self, method: &str, result: Value) {
+        self.results.lock().unwrap().insert(method.to_string(), result);
+    }
+}
+
+fn handle(
+    stream: TcpStream,
+    results: &HashMap<String, Value>,
+    errors: &HashMap<String, Value>,
+    calls: &Mutex<Vec<String>>,
+) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut content_length = 0;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).unwrap_or(0) == 0 || line == "\r\n" {
+            break;
+        }
+        let lower = line.to_ascii_lowercase();
+        if let Some(value) = lower.strip_prefix("content-length:") {
+            content_length = value.trim().parse().unwrap_or(0);
+        }
+    }
+    let mut body = vec![0u8; content_length];
+    if reader.read_exact(&mut body).is_err() {
+        return;
+    }
+
+    let requests: Vec<Value> = serde_json::from_slice(&body).unwrap_or_default();
+    let responses: Vec<Value> = requests.iter().map(|request| {
+        let method = request["method"].as_str().unwrap_or_default().to_string();
+        calls.lock().unwrap().push(method.clone());
+        if let Some(error) = errors.get(&method) {
+            return json!({ "jsonrpc": "2.0", "id": request["id"], "error": error });
+        }
+        json!({
+            "jsonrpc": "2.0",
+            "id": request["id"],
+            "result": results.get(&method).cloned().unwrap_or(Value::Null),
+        })
+    }).collect();
+
+    let payload = Value::Array(responses).to_string();
+    let mut stream = stream;
+    let _ = write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        payload.len(),
+        payload
+    );
+}
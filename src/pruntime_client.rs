@@ -1,43 +1,177 @@
 use serde::Serialize;
 use hyper::Client as HttpClient;
+use hyper::client::HttpConnector;
+use hyper::client::connect::Connect;
 use hyper::{Body, Method, Request};
-use bytes::buf::BufExt as _;
+use hyper::body::HttpBody as _;
+use async_trait::async_trait;
+use flate2::{Compression, write::GzEncoder};
+use std::io::Write;
 
 use crate::error::Error;
+use crate::pruntime_pool::WorkerStats;
 use crate::types::{
     RuntimeReq, Resp, SignedResp, Payload, Query, QueryReq, QueryReqData, QueryRespData
 };
 
-pub struct PRuntimeClient {
-    base_url: String
+/// The pRuntime operations the bridge depends on, so it can run against a mock in tests
+#[async_trait]
+pub trait PRuntime: Send + Sync {
+    async fn query(&self, contract_id: u32, request: QueryReqData) -> Result<QueryRespData, Error>;
+
+    /// Per worker counters, for pools of several workers
+    fn worker_stats(&self) -> Vec<WorkerStats> {
+        Vec::new()
+    }
+}
+
+/// How request bodies sent to pRuntime are encoded
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BodyEncoding {
+    Json,
+    /// BCS, for pRuntimes accepting binary bodies
+    Bcs,
+}
+
+impl BodyEncoding {
+    fn content_type(&self) -> &'static str {
+        match self {
+            BodyEncoding::Json => "application/json",
+            BodyEncoding::Bcs => "application/x-bcs",
+        }
+    }
+}
+
+impl std::str::FromStr for BodyEncoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "json" => Ok(BodyEncoding::Json),
+            "bcs" => Ok(BodyEncoding::Bcs),
+            _ => Err(format!("unknown body encoding: {}, expected json or bcs", s)),
+        }
+    }
+}
+
+pub const DEFAULT_MAX_RESPONSE_BYTES: usize = 16 * 1024 * 1024;
+
+/// A pRuntime http client, over any hyper connector (`C`), e.g. a unix socket or a proxy
+pub struct PRuntimeClient<C = HttpConnector> {
+    client: HttpClient<C>,
+    base_url: String,
+    compress: bool,
+    headers: Vec<(String, String)>,
+    encoding: BodyEncoding,
+    max_response_bytes: usize,
 }
 
 impl PRuntimeClient {
-    pub fn new(base_url: &str) -> Self {
+    /// Creates a client for the pRuntime at `base_url`
+    ///
+    /// When `compress` is set, request bodies are gzipped and sent with `content-encoding: gzip`.
+    /// `headers` are attached to every request, e.g. for routing through an API gateway.
+    pub fn new(base_url: &str, compress: bool, headers: Vec<(String, String)>) -> Self {
+        PRuntimeClient::new_with_client(base_url, HttpClient::new())
+            .with_compression(compress)
+            .with_headers(headers)
+    }
+}
+
+impl<C> PRuntimeClient<C> where C: Connect + Clone + Send + Sync + 'static {
+    /// Creates a client for the pRuntime at `base_url` sending its requests through `client`
+    pub fn new_with_client(base_url: &str, client: HttpClient<C>) -> Self {
         PRuntimeClient {
-            base_url: base_url.to_string()
+            client,
+            base_url: base_url.to_string(),
+            compress: false,
+            headers: Vec::new(),
+            encoding: BodyEncoding::Json,
+            max_response_bytes: DEFAULT_MAX_RESPONSE_BYTES,
+        }
+    }
+
+    /// Gzips request bodies, sending them with `content-encoding: gzip`
+    pub fn with_compression(mut self, compress: bool) -> Self {
+        self.compress = compress;
+        self
+    }
+
+    /// Attaches `headers` to every request
+    pub fn with_headers(mut self, headers: Vec<(String, String)>) -> Self {
+        self.headers = headers;
+        self
+    }
+
+    /// Encodes request bodies with `encoding` instead of JSON; responses are always JSON
+    pub fn with_encoding(mut self, encoding: BodyEncoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Aborts responses with a body larger than `max_response_bytes`
+    pub fn with_max_response_bytes(mut self, max_response_bytes: usize) -> Self {
+        self.max_response_bytes = max_response_bytes;
+        self
+    }
+
+    /// Reads the body chunk by chunk, so an oversized one is rejected before it's all in memory
+    async fn read_body(&self, mut body: Body) -> Result<Vec<u8>, Error> {
+        let mut data = Vec::new();
+        while let Some(chunk) = body.data().await {
+            let chunk = chunk?;
+            if data.len() + chunk.len() > self.max_response_bytes {
+                return Err(Error::ResponseTooLarge { limit: self.max_response_bytes });
+            }
+            data.extend_from_slice(&chunk);
         }
+        Ok(data)
+    }
+
+    fn encode_body<T: Serialize>(&self, param: &T) -> Result<Vec<u8>, Error> {
+        let body = match self.encoding {
+            BodyEncoding::Json => serde_json::to_vec(param)?,
+            BodyEncoding::Bcs => bcs::to_bytes(param).map_err(|_| Error::FailedToEncode)?,
+        };
+        if !self.compress {
+            return Ok(body);
+        }
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&body).map_err(|_| Error::FailedToEncode)?;
+        encoder.finish().map_err(|_| Error::FailedToEncode)
     }
 
     async fn req<T>(&self, command: &str, param: &T) -> Result<SignedResp, Error>  where T: Serialize {
-        let client = HttpClient::new();
         let endpoint = format!("{}/{}", self.base_url, command);
 
-        let body_json = serde_json::to_string(param)?;
+        let body = self.encode_body(param)?;
 
-        let req = Request::builder()
+        let mut builder = Request::builder()
             .method(Method::POST)
             .uri(endpoint)
-            .header("content-type", "application/json")
-            .body(Body::from(body_json))?;
+            .header("content-type", self.encoding.content_type())
+            .header("accept", "application/json");
+        if self.compress {
+            builder = builder.header("content-encoding", "gzip");
+        }
+        for (name, value) in &self.headers {
+            builder = builder.header(name.as_str(), value.as_str());
+        }
+        let req = builder.body(Body::from(body))?;
 
-        let res = client.request(req).await?;
+        let res = self.client.request(req).await?;
 
         println!("Response: {}", res.status());
+        check_content_type(res.headers().get("content-type").map(|v| v.to_str().unwrap_or_default()))?;
 
-        let body = hyper::body::aggregate(res.into_body()).await?;
-        //println!("Body: {}", body);
-        let signed_resp: SignedResp = serde_json::from_reader(body.reader())?;
+        let content_length = res.headers().get("content-length")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<usize>().ok());
+        if content_length.map_or(false, |len| len > self.max_response_bytes) {
+            return Err(Error::ResponseTooLarge { limit: self.max_response_bytes });
+        }
+        let body = self.read_body(res.into_body()).await?;
+        let signed_resp: SignedResp = serde_json::from_slice(&body)?;
 
         // TODO: validate the response from pRuntime
 
@@ -79,3 +213,122 @@ impl PRuntimeClient {
     }
 
 }
+
+/// Rejects non-JSON responses up front, so a protocol mismatch isn't reported as a parse failure
+///
+/// A missing content-type is accepted, older pRuntimes don't send one.
+fn check_content_type(content_type: Option<&str>) -> Result<(), Error> {
+    match content_type {
+        Some(content_type) if !content_type.trim_start().starts_with("application/json") => {
+            Err(Error::UnexpectedContentType(content_type.to_string()))
+        }
+        _ => Ok(()),
+    }
+}
+
+#[async_trait]
+impl<C> PRuntime for PRuntimeClient<C> where C: Connect + Clone + Send + Sync + 'static {
+    async fn query(&self, contract_id: u32, request: QueryReqData) -> Result<QueryRespData, Error> {
+        PRuntimeClient::query(self, contract_id, request).await
+    }
+}
+
+/// An in-memory pRuntime recording every query and answering with scripted responses
+#[cfg(test)]
+pub struct MockPRuntime {
+    pub requests: std::sync::Mutex<Vec<(u32, QueryReqData)>>,
+    responses: std::sync::Mutex<std::collections::VecDeque<QueryRespData>>,
+}
+
+#[cfg(test)]
+impl MockPRuntime {
+    pub fn new(responses: Vec<QueryRespData>) -> Self {
+        MockPRuntime {
+            requests: std::sync::Mutex::new(Vec::new()),
+            responses: std::sync::Mutex::new(responses.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+#[async_trait]
+impl PRuntime for MockPRuntime {
+    async fn query(&self, contract_id: u32, request: QueryReqData) -> Result<QueryRespData, Error> {
+        self.requests.lock().unwrap().push((contract_id, request));
+        self.responses.lock().unwrap().pop_front().ok_or(Error::FailedToGetResponse)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    #[test]
+    fn compressed_body_round_trip() {
+        let query = QueryReq { query_payload: "a".repeat(4096) };
+        let body_json = serde_json::to_string(&query).unwrap();
+
+        let plain = PRuntimeClient::new("http://127.0.0.1:8000", false, vec![]);
+        assert_eq!(plain.encode_body(&query).unwrap(), body_json.as_bytes());
+
+        let gzip = PRuntimeClient::new("http://127.0.0.1:8000", true, vec![]);
+        let compressed = gzip.encode_body(&query).unwrap();
+        assert!(compressed.len() < body_json.len());
+
+        let mut decoded = String::new();
+        GzDecoder::new(&compressed[..]).read_to_string(&mut decoded).unwrap();
+        assert_eq!(decoded, body_json);
+    }
+
+    #[test]
+    fn bcs_body_and_content_type_check() {
+        let query = QueryReq { query_payload: "a".to_string() };
+        let client = PRuntimeClient::new("http://127.0.0.1:8000", false, vec![]).with_encoding(BodyEncoding::Bcs);
+        assert_eq!(client.encode_body(&query).unwrap(), bcs::to_bytes(&query).unwrap());
+
+        assert!(check_content_type(None).is_ok());
+        assert!(check_content_type(Some("application/json; charset=utf-8")).is_ok());
+        assert!(matches!(check_content_type(Some("text/html")), Err(Error::UnexpectedContentType(_))));
+    }
+
+    /// Answers a single request with a signed response carrying `resp`, padded with `padding`
+    fn serve_once(resp: QueryRespData, padding: usize) -> String {
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0u8; 4096];
+            let _ = stream.read(&mut request);
+            let plain = serde_json::to_string(&resp).unwrap();
+            let payload = serde_json::to_string(&Payload::Plain(plain)).unwrap();
+            let body = serde_json::json!({ "payload": payload, "status": "a".repeat(padding) }).to_string();
+            let _ = write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+        });
+        url
+    }
+
+    #[tokio::test]
+    async fn query_through_injected_client() {
+        let url = serve_once(QueryRespData::GetEventCursor { sequence: Some(3) }, 0);
+        let client = PRuntimeClient::new_with_client(&url, HttpClient::builder().build_http());
+        let resp = client.query(5, QueryReqData::GetEventCursor { event_key: "00".to_string() }).await;
+        assert!(matches!(resp, Ok(QueryRespData::GetEventCursor { sequence: Some(3) })));
+    }
+
+    #[tokio::test]
+    async fn oversized_response_rejected() {
+        let url = serve_once(QueryRespData::GetEventCursor { sequence: Some(3) }, 4096);
+        let client = PRuntimeClient::new(&url, false, vec![]).with_max_response_bytes(1024);
+        let resp = client.query(5, QueryReqData::GetEventCursor { event_key: "00".to_string() }).await;
+        assert!(matches!(resp, Err(Error::ResponseTooLarge { limit: 1024 })));
+    }
+}
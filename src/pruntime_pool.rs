@@ -0,0 +1,186 @@
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use async_trait::async_trait;
+
+use crate::error::Error;
+use crate::pruntime_client::PRuntime;
+use crate::types::{QueryReqData, QueryRespData};
+
+/// How a `PRuntimePool` spreads queries over its workers
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PoolMode {
+    /// Every query goes to all workers and needs a quorum of them to give the same answer
+    Fanout,
+    /// Each query goes to the next worker, failing over to the following ones
+    RoundRobin,
+}
+
+impl FromStr for PoolMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "fanout" => Ok(PoolMode::Fanout),
+            "roundrobin" => Ok(PoolMode::RoundRobin),
+            _ => Err(format!("unknown pruntime mode: {}, expected fanout or roundrobin", s)),
+        }
+    }
+}
+
+/// Queries answered and failed by one worker since the bridge started
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WorkerStats {
+    pub successes: u64,
+    pub failures: u64,
+    /// Fanout answers that differed from the one the quorum agreed on
+    pub disagreements: u64,
+}
+
+/// Several pRuntime workers hosting the same Diem contract, used as one
+pub struct PRuntimePool<P: PRuntime> {
+    workers: Vec<P>,
+    mode: PoolMode,
+    quorum: usize,
+    next: AtomicUsize,
+    stats: Mutex<Vec<WorkerStats>>,
+}
+
+impl<P: PRuntime> PRuntimePool<P> {
+    /// `quorum` only applies to `PoolMode::Fanout` and is clamped to the number of workers
+    pub fn new(workers: Vec<P>, mode: PoolMode, quorum: usize) -> Result<Self, Error> {
+        if workers.is_empty() {
+            return Err(Error::InvalidConfig("at least one pRuntime endpoint is required".to_string()));
+        }
+        let quorum = quorum.max(1).min(workers.len());
+        let stats = Mutex::new(vec![WorkerStats::default(); workers.len()]);
+        Ok(PRuntimePool { workers, mode, quorum, next: AtomicUsize::new(0), stats })
+    }
+
+    pub fn stats(&self) -> Vec<WorkerStats> {
+        self.stats.lock().unwrap().clone()
+    }
+
+    async fn query_worker(&self, index: usize, contract_id: u32, request: QueryReqData) -> Result<QueryRespData, Error> {
+        let result = self.workers[index].query(contract_id, request).await;
+        let mut stats = self.stats.lock().unwrap();
+        match &result {
+            Ok(_) => stats[index].successes += 1,
+            Err(e) => {
+                println!("pRuntime worker {} failed: {:?}", index, e);
+                stats[index].failures += 1;
+            }
+        }
+        result
+    }
+}
+
+#[async_trait]
+impl<P: PRuntime> PRuntime for PRuntimePool<P> {
+    async fn query(&self, contract_id: u32, request: QueryReqData) -> Result<QueryRespData, Error> {
+        match self.mode {
+            PoolMode::RoundRobin => {
+                let start = self.next.fetch_add(1, Ordering::Relaxed);
+                let mut last_error = Error::FailedToGetResponse;
+                for i in 0..self.workers.len() {
+                    let index = (start + i) % self.workers.len();
+                    match self.query_worker(index, contract_id, request.clone()).await {
+                        Ok(resp) => return Ok(resp),
+                        Err(e) => last_error = e,
+                    }
+                }
+                Err(last_error)
+            }
+            PoolMode::Fanout => {
+                let mut answers = Vec::new();
+                for index in 0..self.workers.len() {
+                    if let Ok(resp) = self.query_worker(index, contract_id, request.clone()).await {
+                        let value = serde_json::to_value(&resp)?;
+                        answers.push((index, resp, value));
+                    }
+                }
+                // Only equal answers count towards the quorum, take the one most workers gave
+                let agreeing = |value: &serde_json::Value| answers.iter().filter(|(_, _, v)| v == value).count();
+                let majority = match answers.iter().max_by_key(|(_, _, value)| agreeing(value)) {
+                    Some((_, _, value)) => value.clone(),
+                    None => return Err(Error::QuorumNotReached { successes: 0, quorum: self.quorum }),
+                };
+                let successes = agreeing(&majority);
+                if successes < self.quorum {
+                    return Err(Error::QuorumNotReached { successes, quorum: self.quorum });
+                }
+                {
+                    let mut stats = self.stats.lock().unwrap();
+                    for (index, resp, _) in answers.iter().filter(|(_, _, value)| *value != majority) {
+                        println!("pRuntime worker {} disagrees with the quorum: {:?}", index, resp);
+                        stats[*index].disagreements += 1;
+                    }
+                }
+                let (_, resp, _) = answers.into_iter().find(|(_, _, value)| *value == majority)
+                    .expect("the majority answer is one of the answers");
+                Ok(resp)
+            }
+        }
+    }
+
+    fn worker_stats(&self) -> Vec<WorkerStats> {
+        self.stats()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pruntime_client::MockPRuntime;
+
+    fn cursor(sequence: u64) -> QueryRespData {
+        QueryRespData::GetEventCursor { sequence: Some(sequence) }
+    }
+
+    fn request() -> QueryReqData {
+        QueryReqData::GetEventCursor { event_key: "00".to_string() }
+    }
+
+    #[tokio::test]
+    async fn round_robin_fails_over() {
+        let pool = PRuntimePool::new(vec![
+            MockPRuntime::new(vec![cursor(1), cursor(2)]),
+            MockPRuntime::new(vec![]),
+        ], PoolMode::RoundRobin, 1).unwrap();
+
+        assert!(matches!(pool.query(5, request()).await, Ok(QueryRespData::GetEventCursor { sequence: Some(1) })));
+        // The second worker has nothing scripted and fails, the query fails over to the first one
+        assert!(matches!(pool.query(5, request()).await, Ok(QueryRespData::GetEventCursor { sequence: Some(2) })));
+        assert_eq!(pool.stats(), vec![
+            WorkerStats { successes: 2, failures: 0, disagreements: 0 },
+            WorkerStats { successes: 0, failures: 1, disagreements: 0 },
+        ]);
+    }
+
+    #[tokio::test]
+    async fn fanout_needs_quorum() {
+        let pool = PRuntimePool::new(vec![
+            MockPRuntime::new(vec![cursor(1), cursor(2)]),
+            MockPRuntime::new(vec![cursor(1)]),
+            MockPRuntime::new(vec![]),
+        ], PoolMode::Fanout, 2).unwrap();
+
+        assert!(pool.query(5, request()).await.is_ok());
+        assert!(matches!(pool.query(5, request()).await, Err(Error::QuorumNotReached { successes: 1, quorum: 2 })));
+        assert_eq!(pool.workers[0].requests.lock().unwrap().len(), 2);
+    }
+    #[tokio::test]
+    async fn fanout_quorum_counts_equal_answers() {
+        let pool = PRuntimePool::new(vec![
+            MockPRuntime::new(vec![cursor(1), cursor(1)]),
+            MockPRuntime::new(vec![cursor(2), cursor(1)]),
+            MockPRuntime::new(vec![cursor(3), cursor(4)]),
+        ], PoolMode::Fanout, 2).unwrap();
+
+        // Every worker answered, but no two alike
+        assert!(matches!(pool.query(5, request()).await, Err(Error::QuorumNotReached { successes: 1, quorum: 2 })));
+        assert!(matches!(pool.query(5, request()).await, Ok(QueryRespData::GetEventCursor { sequence: Some(1) })));
+        assert_eq!(pool.stats()[0], WorkerStats { successes: 2, failures: 0, disagreements: 0 });
+        assert_eq!(pool.stats()[2], WorkerStats { successes: 2, failures: 0, disagreements: 1 });
+    }
+}
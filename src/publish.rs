@@ -0,0 +1,125 @@
+//! Broadcasts verified transactions to local subscribers over a Unix domain socket
+
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::error::Error;
+
+/// Accepts subscribers on a Unix socket and writes every published line to all of them
+///
+/// Subscribers are written to without blocking: one that can't take a whole line right away is
+/// dropped, so a slow reader never holds up the sync loop.
+pub struct Publisher {
+    path: String,
+    subscribers: Arc<Mutex<Vec<UnixStream>>>,
+}
+
+impl Publisher {
+    /// Binds the socket at `path`, replacing a stale socket file left by a previous run
+    ///
+    /// Anything else at `path`, including a socket another process still listens on, is left
+    /// alone and reported as an error.
+    pub fn bind(path: &str) -> Result<Self, Error> {
+        if let Ok(metadata) = fs::symlink_metadata(path) {
+            if !metadata.file_type().is_socket() {
+                return Err(Error::InvalidConfig(format!("{} exists and isn't a socket", path)));
+            }
+            if UnixStream::connect(path).is_ok() {
+                return Err(Error::InvalidConfig(format!("{} is in use by another publisher", path)));
+            }
+            fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)?;
+        let subscribers = Arc::new(Mutex::new(Vec::new()));
+
+        let accepted = subscribers.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream.and_then(|s| s.set_nonblocking(true).map(|_| s)) {
+                    Ok(stream) => accepted.lock().unwrap().push(stream),
+                    Err(e) => println!("publish socket accept error: {:?}", e),
+                }
+            }
+        });
+
+        Ok(Publisher { path: path.to_string(), subscribers })
+    }
+
+    pub fn subscribers(&self) -> usize {
+        self.subscribers.lock().unwrap().len()
+    }
+
+    /// Writes `line` and a newline to every subscriber, dropping those that fail or lag
+    pub fn publish(&self, line: &str) {
+        let mut data = line.as_bytes().to_vec();
+        data.push(b'\n');
+        self.subscribers.lock().unwrap().retain(|mut stream| {
+            // A partial write would leave the reader with a broken line, drop it as well
+            match stream.write(&data) {
+                Ok(written) if written == data.len() => true,
+                result => {
+                    println!("dropping publish subscriber: {:?}", result.map(|_| "slow reader"));
+                    false
+                }
+            }
+        });
+    }
+}
+
+impl Drop for Publisher {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::time::Duration;
+
+    #[test]
+    fn broadcasts_to_every_subscriber() {
+        let path = std::env::temp_dir().join(format!("pdiem-publish-{}.sock", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        let publisher = Publisher::bind(&path).unwrap();
+
+        let readers: Vec<_> = (0..2).map(|_| BufReader::new(UnixStream::connect(&path).unwrap())).collect();
+        while publisher.subscribers() < 2 {
+            thread::sleep(Duration::from_millis(10));
+        }
+        publisher.publish("{\"version\":5}");
+
+        for mut reader in readers {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            assert_eq!(line, "{\"version\":5}\n");
+        }
+
+        drop(publisher);
+        assert!(fs::metadata(&path).is_err());
+    }
+
+    #[test]
+    fn refuses_to_replace_other_files() {
+        let path = std::env::temp_dir().join(format!("pdiem-publish-{}.json", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        fs::write(&path, "cursors").unwrap();
+
+        assert!(matches!(Publisher::bind(&path), Err(Error::InvalidConfig(_))));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "cursors");
+        fs::remove_file(&path).unwrap();
+
+        // A socket left behind by a previous run is replaced, a live one isn't
+        let path = std::env::temp_dir().join(format!("pdiem-publish-stale-{}.sock", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        drop(UnixListener::bind(&path).unwrap());
+        let publisher = Publisher::bind(&path).unwrap();
+        assert!(matches!(Publisher::bind(&path), Err(Error::InvalidConfig(_))));
+        drop(publisher);
+    }
+}
@@ -0,0 +1,176 @@
+use std::cmp;
+use std::time::{Duration, SystemTime};
+use diem_json_rpc_client::errors::{JsonRpcError, ServerCode};
+use rand::Rng;
+
+pub const DEFAULT_RPC_RETRIES: u32 = 2;
+pub const DEFAULT_RETRY_BUDGET: u32 = 20;
+pub const DEFAULT_RETRY_BUDGET_REFILL_PER_SEC: f64 = 1.0;
+const INTERNAL_ERROR_CODE: i16 = -32603;
+/// Backoff before the first retry, doubled on each of the following ones
+pub const DEFAULT_RETRY_BACKOFF: Duration = Duration::from_millis(100);
+pub const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(5);
+
+/// Why a Diem json-rpc request failed, as seen by a `RetryPolicy`
+#[derive(Debug)]
+pub enum RpcFailure<'a> {
+    /// No usable http response, e.g. connection refused or a malformed batch response
+    Transport(&'a anyhow::Error),
+    /// The node answered the request with a json-rpc error object
+    Rpc(&'a JsonRpcError),
+    /// The node answered the batch without a response for the request
+    MissingResponse,
+    /// The node no longer has the state at the requested version, see `is_pruned_error`
+    Pruned(&'a JsonRpcError),
+}
+
+/// Decides which failed rpc requests are worth sending again
+///
+/// Nodes differ in the errors they return for "try again later", so deployments against
+/// nonstandard nodes can plug in their own classification.
+pub trait RetryPolicy: Send + Sync {
+    fn should_retry(&self, failure: &RpcFailure) -> bool;
+
+    /// How long to wait before the `attempt`th retry, counted from 1
+    fn backoff(&self, attempt: u32) -> Duration {
+        exponential_backoff(attempt)
+    }
+}
+
+/// `DEFAULT_RETRY_BACKOFF` doubled for every retry up to `MAX_RETRY_BACKOFF`, half of it random
+/// so that bridges failing together don't retry in lockstep
+pub fn exponential_backoff(attempt: u32) -> Duration {
+    let ceiling = DEFAULT_RETRY_BACKOFF.checked_mul(1 << cmp::min(attempt.saturating_sub(1), 16))
+        .map_or(MAX_RETRY_BACKOFF, |backoff| cmp::min(backoff, MAX_RETRY_BACKOFF));
+    let half = ceiling / 2;
+    half + half.mul_f64(rand::thread_rng().gen::<f64>())
+}
+
+/// Retries transport failures and the server errors a standard Diem node returns when it's
+/// temporarily unable to answer; malformed requests and VM errors are permanent.
+pub struct DefaultRetryPolicy;
+
+impl RetryPolicy for DefaultRetryPolicy {
+    fn should_retry(&self, failure: &RpcFailure) -> bool {
+        match failure {
+            RpcFailure::Transport(_) | RpcFailure::MissingResponse => true,
+            // Another attempt gets the same answer, the archive endpoint is the place to go
+            RpcFailure::Pruned(_) => false,
+            RpcFailure::Rpc(error) => {
+                error.code == ServerCode::DefaultServerError as i16
+                    || error.code == ServerCode::MempoolIsFull as i16
+                    || error.code == ServerCode::MempoolTooManyTransactions as i16
+                    // the json-rpc internal error, left out of `InvalidRequestCode`
+                    || error.code == INTERNAL_ERROR_CODE
+            }
+        }
+    }
+}
+
+/// Token bucket bounding the retries of the whole bridge, so a degraded endpoint gets at most
+/// `capacity` retries in a burst and `refill_per_sec` sustained, however many calls fail
+#[derive(Debug, Clone)]
+pub struct RetryBudget {
+    capacity: f64,
+    refill_per_sec: f64,
+    tokens: f64,
+    refilled_at: Option<SystemTime>,
+}
+
+impl RetryBudget {
+    pub fn new(capacity: u32, refill_per_sec: f64) -> Self {
+        RetryBudget {
+            capacity: capacity as f64,
+            refill_per_sec,
+            tokens: capacity as f64,
+            refilled_at: None,
+        }
+    }
+
+    /// Takes one retry from the budget, `false` means the caller must fail instead of retrying
+    pub fn try_acquire(&mut self, now: SystemTime) -> bool {
+        if let Some(elapsed) = self.refilled_at.and_then(|at| now.duration_since(at).ok()) {
+            self.tokens = (self.tokens + elapsed.as_secs_f64() * self.refill_per_sec).min(self.capacity);
+        }
+        self.refilled_at = Some(now);
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
+
+/// Whether the node refused a historical query because it pruned the requested version
+///
+/// Diem nodes report pruning as a generic server error, only the message tells it apart.
+pub fn is_pruned_error(error: &JsonRpcError) -> bool {
+    error.message.to_ascii_lowercase().contains("pruned")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rpc_error(code: i16) -> JsonRpcError {
+        JsonRpcError { code, message: String::new(), data: None }
+    }
+
+    #[test]
+    fn default_policy_classification() {
+        let policy = DefaultRetryPolicy;
+        let transport = anyhow::anyhow!("connection refused");
+        assert!(policy.should_retry(&RpcFailure::Transport(&transport)));
+        assert!(policy.should_retry(&RpcFailure::MissingResponse));
+        assert!(policy.should_retry(&RpcFailure::Rpc(&rpc_error(-32000))));
+        assert!(policy.should_retry(&RpcFailure::Rpc(&rpc_error(-32603))));
+        assert!(policy.should_retry(&RpcFailure::Rpc(&rpc_error(-32008))));
+
+        assert!(!policy.should_retry(&RpcFailure::Rpc(&rpc_error(-32602))));
+        assert!(!policy.should_retry(&RpcFailure::Rpc(&rpc_error(-32601))));
+        assert!(!policy.should_retry(&RpcFailure::Rpc(&rpc_error(-32001))));
+
+        let pruned = JsonRpcError { code: -32000, message: "Version 3 has been pruned".to_string(), data: None };
+        assert!(is_pruned_error(&pruned));
+        assert!(!policy.should_retry(&RpcFailure::Pruned(&pruned)));
+    }
+
+    #[test]
+    fn budget_refills_over_time() {
+        use std::time::Duration;
+
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1000);
+        let mut budget = RetryBudget::new(2, 0.5);
+        assert!(budget.try_acquire(start));
+        assert!(budget.try_acquire(start));
+        assert!(!budget.try_acquire(start));
+
+        assert!(!budget.try_acquire(start + Duration::from_secs(1)));
+        assert!(budget.try_acquire(start + Duration::from_secs(2)));
+        assert!(!budget.try_acquire(start + Duration::from_secs(2)));
+        // Refills stop at the capacity
+        assert!(budget.try_acquire(start + Duration::from_secs(100)));
+        assert!(budget.try_acquire(start + Duration::from_secs(100)));
+        assert!(!budget.try_acquire(start + Duration::from_secs(100)));
+    }
+
+    #[test]
+    fn backoff_grows_up_to_the_cap() {
+        for attempt in 1..40 {
+            let ceiling = std::cmp::min(DEFAULT_RETRY_BACKOFF * 2u32.pow(std::cmp::min(attempt - 1, 16)), MAX_RETRY_BACKOFF);
+            let backoff = DefaultRetryPolicy.backoff(attempt);
+            assert!(backoff >= ceiling / 2 && backoff <= ceiling, "attempt {}: {:?}", attempt, backoff);
+        }
+    }
+
+    #[test]
+    fn custom_policy() {
+        struct NeverRetry;
+        impl RetryPolicy for NeverRetry {
+            fn should_retry(&self, _: &RpcFailure) -> bool {
+                false
+            }
+        }
+        assert!(!NeverRetry.should_retry(&RpcFailure::MissingResponse));
+    }
+}
@@ -0,0 +1,58 @@
+use serde::{Serialize, Deserialize};
+use diem_types::{
+    epoch_change::EpochChangeProof,
+    ledger_info::LedgerInfoWithSignatures,
+};
+
+use crate::error::Error;
+use crate::state_file::SyncCursors;
+
+const SNAPSHOT_MAGIC: &[u8; 4] = b"pdsn";
+const SNAPSHOT_VERSION: u8 = 1;
+
+/// The bridge state a standby instance needs to take over without re-bootstrapping
+///
+/// The trusted state itself isn't serializable; it is rebuilt from the latest epoch change
+/// ledger info and ratcheted to `latest_li` on import.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BridgeSnapshot {
+    pub latest_epoch_change_li: LedgerInfoWithSignatures,
+    pub latest_li: LedgerInfoWithSignatures,
+    pub epoch_change_proof: EpochChangeProof,
+    pub cursors: SyncCursors,
+}
+
+impl BridgeSnapshot {
+    /// Encodes the snapshot as BCS behind a magic and format version header
+    pub fn encode(&self) -> Result<Vec<u8>, Error> {
+        let mut data = SNAPSHOT_MAGIC.to_vec();
+        data.push(SNAPSHOT_VERSION);
+        data.extend(bcs::to_bytes(self).map_err(|_| Error::FailedToEncode)?);
+        Ok(data)
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self, Error> {
+        let header_len = SNAPSHOT_MAGIC.len() + 1;
+        if data.len() < header_len || &data[..SNAPSHOT_MAGIC.len()] != SNAPSHOT_MAGIC {
+            return Err(Error::InvalidSnapshot("missing snapshot header".to_string()));
+        }
+        let version = data[SNAPSHOT_MAGIC.len()];
+        if version != SNAPSHOT_VERSION {
+            return Err(Error::InvalidSnapshot(format!("unsupported snapshot version {}", version)));
+        }
+        bcs::from_bytes(&data[header_len..])
+            .map_err(|e| Error::InvalidSnapshot(format!("bad snapshot body: {}", e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_rejects_bad_header() {
+        assert!(matches!(BridgeSnapshot::decode(b"pd"), Err(Error::InvalidSnapshot(_))));
+        assert!(matches!(BridgeSnapshot::decode(b"xxxx\x01"), Err(Error::InvalidSnapshot(_))));
+        assert!(matches!(BridgeSnapshot::decode(b"pdsn\x02"), Err(Error::InvalidSnapshot(_))));
+    }
+}
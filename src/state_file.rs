@@ -0,0 +1,161 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
+
+use serde::{Serialize, Deserialize};
+
+use crate::error::Error;
+
+/// Per-account sync progress persisted across restarts
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct SyncCursors {
+    /// The next sent transaction sequence number to sync
+    pub sent_sequence: BTreeMap<String, u64>,
+    /// The highest received transaction version submitted to pRuntime
+    pub received_version: BTreeMap<String, u64>,
+    /// The next received event sequence number to fetch
+    #[serde(default)]
+    pub received_sequence: BTreeMap<String, u64>,
+    /// The most recently submitted transaction versions per account, oldest first
+    ///
+    /// Written right after each submission, so a crash in the middle of a pass doesn't
+    /// re-submit the transactions already handled before the cursors moved past them.
+    #[serde(default)]
+    pub submitted: BTreeMap<String, Vec<u64>>,
+    /// Versions skipped because every endpoint pruned the account state at them, left for a
+    /// backfill against an archive node
+    #[serde(default)]
+    pub pruned: BTreeMap<String, Vec<u64>>,
+}
+
+impl SyncCursors {
+    /// Records a submitted version, keeping at most `limit` versions for the account
+    pub fn record_submitted(&mut self, account_address: &str, version: u64, limit: usize) {
+        let versions = self.submitted.entry(account_address.to_string()).or_default();
+        if versions.contains(&version) {
+            return;
+        }
+        versions.push(version);
+        if versions.len() > limit {
+            let excess = versions.len() - limit;
+            versions.drain(..excess);
+        }
+    }
+
+    /// Records a version whose account state was pruned, once
+    pub fn record_pruned(&mut self, account_address: &str, version: u64) {
+        let versions = self.pruned.entry(account_address.to_string()).or_default();
+        if !versions.contains(&version) {
+            versions.push(version);
+        }
+    }
+
+    /// Drops every cursor of `account_address` (hex, any case, `0x` optional), returning whether
+    /// there was any
+    pub fn forget(&mut self, account_address: &str) -> bool {
+        let account_address = account_address.trim_start_matches("0x");
+        let before = self.len();
+        let keep = |address: &String| !address.eq_ignore_ascii_case(account_address);
+        self.sent_sequence.retain(|address, _| keep(address));
+        self.received_version.retain(|address, _| keep(address));
+        self.received_sequence.retain(|address, _| keep(address));
+        self.submitted.retain(|address, _| keep(address));
+        self.pruned.retain(|address, _| keep(address));
+        self.len() < before
+    }
+
+    fn len(&self) -> usize {
+        self.sent_sequence.len() + self.received_version.len() + self.received_sequence.len() + self.submitted.len()
+            + self.pruned.len()
+    }
+
+    pub fn load(path: &str) -> Result<Self, Error> {
+        if !Path::new(path).exists() {
+            return Ok(SyncCursors::default());
+        }
+        let data = fs::read_to_string(path)?;
+        let cursors = serde_json::from_str(&data)?;
+        Ok(cursors)
+    }
+
+    /// Writes the cursors atomically, see `write_atomic`
+    pub fn save(&self, path: &str) -> Result<(), Error> {
+        let data = serde_json::to_string_pretty(self)?;
+        write_atomic(path, data.as_bytes())
+    }
+}
+
+/// Writes `data` to `path` atomically: a crash never leaves a half-written file behind
+///
+/// The parent directory is synced after the rename as well, otherwise the rename itself may not
+/// survive a power loss and the old file comes back.
+pub fn write_atomic(path: &str, data: &[u8]) -> Result<(), Error> {
+    let tmp_path = format!("{}.tmp", path);
+    let mut file = File::create(&tmp_path)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, path)?;
+    let dir = match Path::new(path).parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let path = std::env::temp_dir().join(format!("pdiem-state-{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        let mut cursors = SyncCursors::default();
+        for i in 0..1000u64 {
+            cursors.sent_sequence.insert(format!("{:032X}", i), i);
+            cursors.received_version.insert(format!("{:032X}", i), i * 10);
+        }
+        cursors.save(path).unwrap();
+        assert!(!Path::new(&format!("{}.tmp", path)).exists());
+
+        let loaded = SyncCursors::load(path).unwrap();
+        assert_eq!(loaded.sent_sequence, cursors.sent_sequence);
+        assert_eq!(loaded.received_version, cursors.received_version);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn submitted_versions_are_bounded() {
+        let mut cursors = SyncCursors::default();
+        for version in 0..5 {
+            cursors.record_submitted("a", version, 3);
+        }
+        cursors.record_submitted("a", 4, 3);
+        assert_eq!(cursors.submitted["a"], vec![2, 3, 4]);
+
+        let data = serde_json::to_string(&cursors).unwrap();
+        let loaded: SyncCursors = serde_json::from_str(&data).unwrap();
+        assert_eq!(loaded.submitted, cursors.submitted);
+        let legacy: SyncCursors = serde_json::from_str(r#"{"sent_sequence":{},"received_version":{}}"#).unwrap();
+        assert!(legacy.submitted.is_empty());
+        assert!(legacy.pruned.is_empty());
+    }
+
+    #[test]
+    fn forget_one_account() {
+        let mut cursors = SyncCursors::default();
+        cursors.sent_sequence.insert("AB".to_string(), 3);
+        cursors.received_version.insert("AB".to_string(), 10);
+        cursors.record_submitted("AB", 10, 8);
+        cursors.sent_sequence.insert("CD".to_string(), 1);
+
+        assert!(cursors.forget("0xab"));
+        assert!(!cursors.received_version.contains_key("AB"));
+        assert!(!cursors.submitted.contains_key("AB"));
+        assert_eq!(cursors.sent_sequence.keys().collect::<Vec<_>>(), vec!["CD"]);
+        assert!(!cursors.forget("ab"));
+    }
+}
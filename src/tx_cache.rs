@@ -0,0 +1,92 @@
+use std::collections::{HashMap, VecDeque};
+use diem_json_rpc_client::views::TransactionView;
+
+pub const DEFAULT_TX_CACHE_SIZE: usize = 1000;
+
+/// Least recently used cache of transactions fetched from the rpc endpoint, keyed by version
+///
+/// Committed transactions never change, so entries only leave the cache by eviction or `clear`.
+pub struct TransactionCache {
+    capacity: usize,
+    transactions: HashMap<u64, TransactionView>,
+    /// Versions from least to most recently used
+    order: VecDeque<u64>,
+}
+
+impl TransactionCache {
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        TransactionCache {
+            capacity,
+            transactions: HashMap::with_capacity(capacity),
+            order: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    pub fn get(&mut self, version: u64) -> Option<TransactionView> {
+        let transaction = self.transactions.get(&version)?.clone();
+        self.touch(version);
+        Some(transaction)
+    }
+
+    /// Caches `transaction`, evicting the least recently used one when the cache is full
+    pub fn insert(&mut self, transaction: TransactionView) {
+        let version = transaction.version;
+        if self.transactions.insert(version, transaction).is_some() {
+            self.touch(version);
+            return;
+        }
+        self.order.push_back(version);
+        if self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.transactions.remove(&oldest);
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.transactions.clear();
+        self.order.clear();
+    }
+
+    fn touch(&mut self, version: u64) {
+        if let Some(pos) = self.order.iter().position(|v| *v == version) {
+            self.order.remove(pos);
+        }
+        self.order.push_back(version);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diem_json_rpc_client::views::{BytesView, TransactionDataView, VMStatusView};
+
+    fn transaction(version: u64) -> TransactionView {
+        TransactionView {
+            version,
+            transaction: TransactionDataView::UnknownTransaction {},
+            hash: String::new(),
+            bytes: BytesView(String::new()),
+            events: vec![],
+            vm_status: VMStatusView::Executed,
+            gas_used: 0,
+        }
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut cache = TransactionCache::new(2);
+        cache.insert(transaction(1));
+        cache.insert(transaction(2));
+        assert!(cache.get(1).is_some());
+
+        cache.insert(transaction(3));
+        assert!(cache.get(2).is_none());
+        assert!(cache.get(1).is_some());
+        assert!(cache.get(3).is_some());
+
+        cache.clear();
+        assert!(cache.get(1).is_none());
+    }
+}
@@ -61,20 +61,23 @@ impl Resp for QueryReq {
 pub enum CommandReqData {
     AccountInfo { account_info_b64: String },
     VerifyTransaction { account_address: String, transaction_with_proof_b64: String },
+    VerifyTransactions { account_address: String, transactions_with_proof_b64: String },
     SetTrustedState { trusted_state_b64: String, chain_id: u8 },
     VerifyEpochProof { ledger_info_with_signatures_b64: String, epoch_change_proof_b64: String },
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum QueryReqData {
     GetSignedTransactions { start: u64 },
     CurrentState,
+    GetEventCursor { event_key: String },
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub enum QueryRespData {
     GetSignedTransactions { queue_b64: String },
     CurrentState { state: State },
+    GetEventCursor { sequence: Option<u64> },
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Encode, Decode)]
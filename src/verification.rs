@@ -0,0 +1,82 @@
+use crate::error::Error;
+
+/// An anomaly found while verifying a transaction proof
+#[derive(Debug, Clone, PartialEq)]
+pub enum VerificationIssue {
+    UndecodableTransaction,
+    BadTransactionHash,
+    MissingAccountResource,
+    /// The account state proven isn't the one of the account asked for
+    AccountMismatch { expected: String, actual: String },
+    AccountStateProofRejected(String),
+    /// The ledger info is accepted without an epoch change proof to back it
+    EmptyEpochChangeProof,
+    /// The transaction is proven by an accumulator with a single leaf
+    EmptyAccumulatorProof,
+}
+
+impl VerificationIssue {
+    /// Fatal issues reject the transaction in every mode, the others only in strict mode
+    pub fn is_fatal(&self) -> bool {
+        match self {
+            VerificationIssue::EmptyEpochChangeProof
+            | VerificationIssue::EmptyAccumulatorProof => false,
+            _ => true,
+        }
+    }
+
+    fn into_error(self) -> Error {
+        match self {
+            VerificationIssue::BadTransactionHash => Error::BadTransactionHash,
+            VerificationIssue::AccountStateProofRejected(reason) => Error::InvalidStateProof(reason),
+            VerificationIssue::AccountMismatch { expected, actual } => Error::AccountMismatch { expected, actual },
+            _ => Error::FailedToDecode,
+        }
+    }
+}
+
+/// Every issue found while verifying the proof of the transaction at `version`
+#[derive(Debug, Clone, Default)]
+pub struct VerificationReport {
+    pub version: u64,
+    pub issues: Vec<VerificationIssue>,
+}
+
+impl VerificationReport {
+    pub fn new(version: u64) -> Self {
+        VerificationReport { version, issues: Vec::new() }
+    }
+
+    pub fn is_clean(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    /// Fails on the first fatal issue, or on any issue at all when `strict`
+    pub fn into_result(self, strict: bool) -> Result<(), Error> {
+        if let Some(issue) = self.issues.iter().find(|i| i.is_fatal()) {
+            return Err(issue.clone().into_error());
+        }
+        if strict && !self.is_clean() {
+            return Err(Error::StrictVerificationFailure(format!("{:?}", self)));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strict_rejects_warnings() {
+        let mut report = VerificationReport::new(7);
+        assert!(report.clone().into_result(true).is_ok());
+
+        report.issues.push(VerificationIssue::EmptyAccumulatorProof);
+        assert!(report.clone().into_result(false).is_ok());
+        assert!(matches!(report.clone().into_result(true), Err(Error::StrictVerificationFailure(_))));
+
+        report.issues.push(VerificationIssue::BadTransactionHash);
+        assert!(matches!(report.into_result(false), Err(Error::BadTransactionHash)));
+    }
+}
@@ -0,0 +1,73 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use crate::clock::Clock;
+
+/// Process exit code used when the watchdog fires
+pub const WATCHDOG_EXIT_CODE: i32 = 3;
+
+/// Dead-man's-switch terminating the process when no sync succeeded within the allowed lag
+///
+/// Unlike an error exit, this also fires when the loop is stuck retrying without ever failing,
+/// so the orchestrator gets a chance to restart the bridge.
+#[derive(Clone)]
+pub struct Watchdog {
+    clock: Arc<dyn Clock>,
+    last_success: Arc<AtomicU64>,
+}
+
+impl Watchdog {
+    pub fn new(clock: Arc<dyn Clock>) -> Self {
+        let now = clock.now_secs();
+        Watchdog {
+            clock,
+            last_success: Arc::new(AtomicU64::new(now)),
+        }
+    }
+
+    /// Records a successful sync
+    pub fn feed(&self) {
+        self.last_success.store(self.clock.now_secs(), Ordering::Relaxed);
+    }
+
+    /// Seconds elapsed since the last successful sync
+    pub fn lag_secs(&self) -> u64 {
+        self.clock.now_secs().saturating_sub(self.last_success.load(Ordering::Relaxed))
+    }
+
+    /// Spawns the background task checking the lag against `max_lag_secs`
+    pub fn spawn(&self, max_lag_secs: u64) {
+        let watchdog = self.clone();
+        tokio::spawn(async move {
+            let period = Duration::from_secs(max_lag_secs.min(60).max(1));
+            loop {
+                watchdog.clock.sleep(period).await;
+                let lag = watchdog.lag_secs();
+                if lag > max_lag_secs {
+                    println!("FATAL: no successful sync for {}s (max {}s), exiting", lag, max_lag_secs);
+                    std::process::exit(WATCHDOG_EXIT_CODE);
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+
+    #[test]
+    fn lag_resets_on_feed() {
+        let clock = Arc::new(MockClock::new(1_000));
+        let watchdog = Watchdog::new(clock.clone());
+        assert_eq!(watchdog.lag_secs(), 0);
+
+        clock.advance(Duration::from_secs(90));
+        assert_eq!(watchdog.lag_secs(), 90);
+
+        watchdog.feed();
+        assert_eq!(watchdog.lag_secs(), 0);
+    }
+}